@@ -0,0 +1,274 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use tokio_util::sync::CancellationToken;
+
+use crate::llm::{LLMProvider, Message, ToolDefinition};
+use crate::permissions::{Approval, ApprovalRequest, Decision, Permissions};
+use crate::shell::{
+    bash_tool_result, execute_bash_streaming, execute_tool, get_tool_definitions, ShellExecutor,
+};
+
+const SYSTEM_PROMPT: &str = "You are an AI assistant that helps users interact with their system through shell commands. \
+    You have access to tools like bash, read, write, and list to help users accomplish their tasks. \
+    When a user asks you to do something, use the appropriate tools to complete the task. \
+    Always explain what you're doing and show the results to the user.";
+
+/// Maximum number of LLM round trips for a single input
+const MAX_ITERATIONS: usize = 10;
+
+/// Tool results longer than this are shortened in transcript summaries
+const SUMMARY_EXCERPT_LEN: usize = 200;
+
+/// Callbacks for rendering what the agent is doing
+#[async_trait]
+pub trait AgentObserver: Send {
+    /// Ask the user whether a tool call may run; refused unless overridden
+    async fn approve(&mut self, _request: &ApprovalRequest) -> Approval {
+        Approval::Deny
+    }
+
+    fn on_tool_call(&mut self, _name: &str, _arguments: &str) {}
+
+    /// Output from a running command, as it arrives
+    fn on_tool_output(&mut self, _name: &str, _chunk: &str) {}
+
+    fn on_tool_result(&mut self, _name: &str, _result: &str) {}
+
+    fn on_response(&mut self, _content: &str) {}
+}
+
+/// An observer that ignores everything
+pub struct SilentObserver;
+
+impl AgentObserver for SilentObserver {}
+
+/// One tool call made while handling an input
+#[derive(Debug, Clone)]
+pub struct ToolStep {
+    pub name: String,
+    pub arguments: String,
+    pub result: String,
+    pub is_error: bool,
+}
+
+/// Outcome of running the agent on one input
+#[derive(Debug, Clone, Default)]
+pub struct AgentRun {
+    pub answer: String,
+    pub steps: Vec<ToolStep>,
+    /// Whether the loop stopped at the iteration limit without an answer
+    pub exhausted: bool,
+}
+
+impl AgentRun {
+    /// A short, one line per tool call account of what happened
+    pub fn transcript_summary(&self) -> String {
+        if self.steps.is_empty() {
+            return "No tools were called.".to_string();
+        }
+
+        self.steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| {
+                let first_line = step.result.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+                format!(
+                    "{}. {}{} {} -> {}",
+                    i + 1,
+                    step.name,
+                    if step.is_error { " (failed)" } else { "" },
+                    excerpt(&step.arguments),
+                    excerpt(first_line)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// `text` cut to `SUMMARY_EXCERPT_LEN` characters
+fn excerpt(text: &str) -> String {
+    let mut excerpt: String = text.chars().take(SUMMARY_EXCERPT_LEN).collect();
+    if excerpt.len() < text.len() {
+        excerpt.push_str("...");
+    }
+    excerpt
+}
+
+/// The tool-calling loop shared by the REPL and the MCP `aishell_agent` tool
+pub struct Agent {
+    llm: Box<dyn LLMProvider>,
+    executor: ShellExecutor,
+    tools: Vec<ToolDefinition>,
+    permissions: Permissions,
+    messages: Vec<Message>,
+}
+
+impl Agent {
+    pub fn new(llm: Box<dyn LLMProvider>, executor: ShellExecutor) -> Self {
+        Self {
+            llm,
+            tools: get_tool_definitions(&executor),
+            executor,
+            permissions: Permissions::default(),
+            messages: vec![Message::system(SYSTEM_PROMPT)],
+        }
+    }
+
+    /// Restrict the tools offered to the model
+    pub fn with_tools(mut self, tools: Vec<ToolDefinition>) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    /// Check tool calls against `permissions` before running them
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    pub fn permissions(&self) -> &Permissions {
+        &self.permissions
+    }
+
+    pub fn executor(&self) -> &ShellExecutor {
+        &self.executor
+    }
+
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    /// Drop the conversation, keeping only the system prompt
+    pub fn clear_history(&mut self) {
+        self.messages.truncate(1);
+    }
+
+    /// Cut the conversation back to its first `len` messages, never
+    /// dropping the system prompt
+    pub fn truncate_history(&mut self, len: usize) {
+        self.messages.truncate(len.max(1));
+    }
+
+    /// Handle one user input, calling tools until the model gives an answer
+    pub async fn run(&mut self, input: &str, observer: &mut dyn AgentObserver) -> Result<AgentRun> {
+        // Add user message
+        self.messages.push(Message::user(input));
+
+        let mut run = AgentRun::default();
+
+        // Agent loop: keep calling LLM until it's done (no more tool calls)
+        for iteration in 0..MAX_ITERATIONS {
+            tracing::debug!("Agent loop iteration {}", iteration + 1);
+
+            let response = self
+                .llm
+                .chat(self.messages.clone(), Some(self.tools.clone()))
+                .await
+                .context("Failed to get LLM response")?;
+
+            // If there are tool calls, execute them
+            if let Some(tool_calls) = response.tool_calls {
+                tracing::info!("LLM requested {} tool calls", tool_calls.len());
+
+                // Add assistant message with tool calls
+                let mut assistant_msg = Message::assistant(response.content.clone());
+                assistant_msg.tool_calls = Some(tool_calls.clone());
+                self.messages.push(assistant_msg);
+
+                // Execute each tool call
+                for tool_call in tool_calls {
+                    let tool_name = &tool_call.function.name;
+                    let tool_args = &tool_call.function.arguments;
+
+                    // Nobody is asked about a tool the model may not use
+                    let permitted = if !self.tools.iter().any(|t| &t.function.name == tool_name) {
+                        Err(anyhow::anyhow!("Tool '{}' is not available", tool_name))
+                    } else {
+                        self.authorize(tool_name, tool_args, observer)
+                            .await
+                            .map_err(|reason| anyhow::anyhow!("Permission denied: {}", reason))
+                    };
+
+                    observer.on_tool_call(tool_name, tool_args);
+
+                    let output = if let Err(e) = permitted {
+                        Err(e)
+                    } else if tool_name == "bash" {
+                        let on_output = |chunk: &str| observer.on_tool_output(tool_name, chunk);
+                        execute_bash_streaming(
+                            tool_args,
+                            &self.executor,
+                            on_output,
+                            CancellationToken::new(),
+                        )
+                        .await
+                        .and_then(|result| bash_tool_result(&result))
+                    } else {
+                        execute_tool(tool_name, tool_args, &self.executor).await
+                    };
+                    let (result, is_error) = match output {
+                        Ok(output) => (output, false),
+                        Err(e) => (format!("Error executing tool: {}", e), true),
+                    };
+
+                    observer.on_tool_result(tool_name, &result);
+
+                    run.steps.push(ToolStep {
+                        name: tool_name.clone(),
+                        arguments: tool_args.clone(),
+                        result: result.clone(),
+                        is_error,
+                    });
+
+                    // Add tool result message
+                    self.messages.push(Message::tool(result, tool_call.id.clone()));
+                }
+
+                // Continue the loop to get the next response
+                continue;
+            }
+
+            // No tool calls, so the LLM is done
+            if !response.content.is_empty() {
+                observer.on_response(&response.content);
+                self.messages.push(Message::assistant(response.content.clone()));
+            }
+            run.answer = response.content;
+
+            return Ok(run);
+        }
+
+        tracing::warn!("Agent stopped after {} iterations", MAX_ITERATIONS);
+        run.exhausted = true;
+        Ok(run)
+    }
+
+    /// Consult the permission rules, asking the observer when they say to
+    async fn authorize(
+        &self,
+        tool_name: &str,
+        tool_args: &str,
+        observer: &mut dyn AgentObserver,
+    ) -> Result<(), String> {
+        let arguments = serde_json::from_str(tool_args).unwrap_or_default();
+        let base = self.executor.current_dir();
+
+        match self.permissions.check(tool_name, &arguments, &base) {
+            Decision::Allow => Ok(()),
+            Decision::Deny(reason) => Err(reason),
+            Decision::Ask(requests) => {
+                for request in &requests {
+                    let approval = observer.approve(request).await;
+                    match self.permissions.resolve(request, approval) {
+                        Ok(true) => {}
+                        Ok(false) => return Err("the user declined this call".to_string()),
+                        // The call was approved even if the rule couldn't be saved
+                        Err(e) => tracing::warn!("Failed to remember permission rule: {:#}", e),
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+use std::io::{IsTerminal, Write};
+use unicode_width::UnicodeWidthChar;
+
+use crate::config::OutputView;
+
+/// Renders a running command's output in the terminal as it arrives
+pub struct LiveOutput {
+    view: OutputView,
+    height: usize,
+    width: usize,
+    /// The last `height` complete lines
+    window: VecDeque<String>,
+    /// Text after the last newline
+    partial: String,
+    /// Number of complete lines seen
+    total: usize,
+    /// Number of terminal rows the tail view currently occupies
+    drawn: usize,
+}
+
+impl LiveOutput {
+    pub fn new(view: OutputView, height: usize) -> Self {
+        let tty = std::io::stdout().is_terminal();
+        // Redrawing a window needs cursor control, so fall back to plain output
+        let view = match view {
+            OutputView::Tail if !tty || height == 0 => OutputView::Full,
+            view => view,
+        };
+
+        Self {
+            view,
+            height,
+            width: terminal_width(),
+            window: VecDeque::with_capacity(height),
+            partial: String::new(),
+            total: 0,
+            drawn: 0,
+        }
+    }
+
+    pub fn push(&mut self, chunk: &str) {
+        match self.view {
+            OutputView::Full => {
+                print!("{}", chunk);
+                self.partial = match chunk.rfind('\n') {
+                    Some(pos) => chunk[pos + 1..].to_string(),
+                    None => format!("{}{}", self.partial, chunk),
+                };
+                self.total += chunk.matches('\n').count();
+            }
+            OutputView::Tail | OutputView::Off => {
+                self.partial.push_str(chunk);
+                while let Some(pos) = self.partial.find('\n') {
+                    let line: String = self.partial.drain(..=pos).collect();
+                    self.push_line(line.trim_end_matches(['\n', '\r']).to_string());
+                }
+                if self.view == OutputView::Tail {
+                    self.redraw(true);
+                }
+            }
+        }
+        let _ = std::io::stdout().flush();
+    }
+
+    /// Stop streaming, collapsing the tail view to its final lines
+    pub fn finish(mut self) {
+        if !self.partial.is_empty() {
+            match self.view {
+                OutputView::Full => println!(),
+                _ => {
+                    let line = std::mem::take(&mut self.partial);
+                    self.push_line(line);
+                }
+            }
+        }
+
+        match self.view {
+            OutputView::Full => {}
+            OutputView::Tail => {
+                self.clear();
+                let hidden = self.total - self.window.len();
+                if hidden > 0 {
+                    println!("\x1b[2m... {} earlier lines not shown\x1b[0m", hidden);
+                }
+                self.redraw(false);
+            }
+            OutputView::Off => {
+                if self.total > 0 {
+                    println!("[{} lines of output]", self.total);
+                }
+            }
+        }
+        let _ = std::io::stdout().flush();
+    }
+
+    fn push_line(&mut self, line: String) {
+        self.total += 1;
+        if self.window.len() == self.height {
+            self.window.pop_front();
+        }
+        self.window.push_back(line);
+    }
+
+    fn redraw(&mut self, dim: bool) {
+        self.clear();
+
+        let partial = (!self.partial.is_empty()).then_some(&self.partial);
+        let skip = match partial {
+            Some(_) if self.window.len() == self.height => 1,
+            _ => 0,
+        };
+        let mut out = std::io::stdout().lock();
+        for line in self.window.iter().skip(skip).chain(partial) {
+            let line = fit(line, self.width);
+            let _ = match dim {
+                true => writeln!(out, "\x1b[2m{}\x1b[0m", line),
+                false => writeln!(out, "{}", line),
+            };
+            self.drawn += 1;
+        }
+    }
+
+    /// Erase the rows drawn by the previous redraw
+    fn clear(&mut self) {
+        if self.drawn > 0 {
+            print!("\x1b[{}F\x1b[J", self.drawn);
+            self.drawn = 0;
+        }
+    }
+}
+
+/// Shorten a line to one terminal row, dropping control characters and
+/// anything a carriage return overwrote. Wide characters, like CJK, take
+/// two columns.
+fn fit(line: &str, width: usize) -> String {
+    let visible = line.rsplit('\r').next().unwrap_or_default();
+    let mut fitted = String::new();
+    let mut columns = 0;
+    for c in visible.replace('\t', "    ").chars().filter(|c| !c.is_control()) {
+        columns += c.width().unwrap_or(0);
+        if columns > width.saturating_sub(1) {
+            break;
+        }
+        fitted.push(c);
+    }
+    fitted
+}
+
+fn terminal_width() -> usize {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ only writes a winsize to the pointer, which is a
+    // live local of that type; a non-terminal stdout just fails with ENOTTY
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    match ok && size.ws_col > 0 {
+        true => size.ws_col as usize,
+        false => 80,
+    }
+}
@@ -1,40 +1,59 @@
 use anyhow::{Context, Result};
+use async_trait::async_trait;
 use rustyline::error::ReadlineError;
 use rustyline::DefaultEditor;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-use crate::llm::{create_provider, LLMProvider, Message};
-use crate::shell::{execute_tool, get_tool_definitions, ShellExecutor};
+use crate::agent::{Agent, AgentObserver};
+use crate::config::{Config, ReplConfig};
+use crate::llm::create_provider;
+use crate::permissions::{Approval, ApprovalRequest, Permissions};
+use crate::prompts::PromptLibrary;
+use crate::shell::{Journal, Reverted, Sandbox, ShellExecutor};
+
+use super::output::LiveOutput;
+
+/// Built-in `/` commands, besides the prompt templates
+const COMMANDS: &[&str] = &["prompts", "reset", "undo", "checkpoint", "rewind"];
 
 pub struct Repl {
-    llm: Box<dyn LLMProvider>,
-    executor: ShellExecutor,
-    messages: Vec<Message>,
+    agent: Agent,
+    prompts: PromptLibrary,
+    settings: ReplConfig,
 }
 
 impl Repl {
-    pub async fn new(provider: &str, model: Option<&str>) -> Result<Self> {
+    /// `sandbox` overrides the configured sandbox profile
+    pub async fn new(provider: &str, model: Option<&str>, sandbox: Option<&str>) -> Result<Self> {
+        let mut config = Config::load()?;
+        if let Some(profile) = sandbox {
+            config.sandbox.profile = profile.to_string();
+        }
         let llm = create_provider(provider, model).await?;
-        let executor = ShellExecutor::default();
-
-        let system_prompt = Message::system(
-            "You are an AI assistant that helps users interact with their system through shell commands. \
-            You have access to tools like bash, read, write, and list to help users accomplish their tasks. \
-            When a user asks you to do something, use the appropriate tools to complete the task. \
-            Always explain what you're doing and show the results to the user."
-        );
+        let executor = ShellExecutor::from_config(&config.shell)?
+            .with_sandbox(Sandbox::from_config(&config.sandbox)?)
+            .with_journal(Journal::default());
+        let prompts = PromptLibrary::load(executor.workdir())?;
+        let permissions = Permissions::load(&config.permissions, executor.workdir())?;
 
         Ok(Self {
-            llm,
-            executor,
-            messages: vec![system_prompt],
+            agent: Agent::new(llm, executor).with_permissions(permissions),
+            prompts,
+            settings: config.repl,
         })
     }
 
     pub async fn run(&mut self) -> Result<()> {
         println!("aishell - AI-powered shell automation");
-        println!("Type 'exit' or 'quit' to exit, 'clear' to clear history\n");
+        println!("Type 'exit' or 'quit' to exit, 'clear' to clear history");
+        println!("Type '/prompts' to list prompt templates, '/<name> [args]' to run one");
+        println!("Type '/reset' to restart the shell session");
+        println!("Type '/undo' to revert the last turn's file changes, '/checkpoint [name]' to mark this point");
+        println!("Type '/rewind' to list turns and checkpoints, '/rewind <n>' to go back to one\n");
 
         let mut rl = DefaultEditor::new()?;
+        self.confirm_project_rules(&mut rl)?;
 
         loop {
             let readline = rl.readline("aishell> ");
@@ -53,13 +72,20 @@ impl Repl {
                     }
 
                     if line == "clear" {
-                        self.messages.truncate(1); // Keep only system message
+                        self.agent.clear_history();
                         println!("History cleared.");
                         continue;
                     }
 
                     rl.add_history_entry(line)?;
 
+                    if let Some(command) = self.command(line) {
+                        if let Err(e) = self.handle_command(command).await {
+                            eprintln!("Error: {}", e);
+                        }
+                        continue;
+                    }
+
                     if let Err(e) = self.process_input(line).await {
                         eprintln!("Error: {}", e);
                     }
@@ -82,67 +108,271 @@ impl Repl {
         Ok(())
     }
 
+    /// Ask whether to trust allow rules in the project's config that the
+    /// user hasn't saved or trusted before; until then they are ignored
+    fn confirm_project_rules(&self, rl: &mut DefaultEditor) -> Result<()> {
+        let permissions = self.agent.permissions();
+        if permissions.untrusted_rules().is_empty() {
+            return Ok(());
+        }
+
+        println!("This project's .aishell/config.toml allows without asking:");
+        for rule in permissions.untrusted_rules() {
+            println!("  {}", rule);
+        }
+        let answer = rl.readline("Trust these rules? [y/N] ").unwrap_or_default();
+        if matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            permissions.trust_project_rules()?;
+            println!("Trusted.\n");
+        } else {
+            println!("Ignoring them; those calls will be checked as usual.\n");
+        }
+        Ok(())
+    }
+
     pub async fn execute_once(&mut self, prompt: &str) -> Result<()> {
-        self.process_input(prompt).await
+        match self.command(prompt) {
+            Some(command) => self.handle_command(command).await,
+            None => self.process_input(prompt).await,
+        }
     }
 
-    async fn process_input(&mut self, input: &str) -> Result<()> {
-        // Add user message
-        self.messages.push(Message::user(input));
+    /// The command in `line` if it starts with `/` and a built-in command
+    /// or prompt name. Anything else, like a path, goes to the agent.
+    fn command<'a>(&self, line: &'a str) -> Option<&'a str> {
+        let command = line.strip_prefix('/')?;
+        let name = command.split_whitespace().next().unwrap_or_default();
+        (COMMANDS.contains(&name) || self.prompts.get(name).is_some()).then_some(command)
+    }
 
-        let tools = get_tool_definitions();
+    /// Handle a `/command`, expanding prompt templates into user input
+    async fn handle_command(&mut self, command: &str) -> Result<()> {
+        let words = shlex::split(command).context("Unbalanced quotes in command")?;
+        let Some((name, args)) = words.split_first() else {
+            return Ok(());
+        };
 
-        // Agent loop: keep calling LLM until it's done (no more tool calls)
-        let max_iterations = 10;
-        for iteration in 0..max_iterations {
-            tracing::debug!("Agent loop iteration {}", iteration + 1);
+        if name == "reset" {
+            match self.agent.executor().persistent_shell() {
+                Some(shell) => {
+                    shell.reset().await;
+                    println!("Shell session reset.");
+                }
+                None => println!("Persistent shell is not enabled (set shell.persistent = true)"),
+            }
+            return Ok(());
+        }
 
-            let response = self
-                .llm
-                .chat(self.messages.clone(), Some(tools.clone()))
-                .await
-                .context("Failed to get LLM response")?;
+        if let Some(journal) = self.agent.executor().journal().cloned() {
+            match name.as_str() {
+                "undo" => {
+                    match journal.undo()? {
+                        Some(reverted) => print_reverted(&reverted),
+                        None => println!("The last turn made no file changes to undo; use /rewind to go back further."),
+                    }
+                    return Ok(());
+                }
+                "checkpoint" => {
+                    let name = (!args.is_empty()).then(|| args.join(" "));
+                    let name = journal.checkpoint(name.as_deref(), self.agent.messages().len());
+                    println!("Created {}.", name);
+                    return Ok(());
+                }
+                "rewind" => {
+                    let Some(n) = args.first() else {
+                        println!("{}", journal.describe());
+                        return Ok(());
+                    };
+                    let n = n.parse().with_context(|| format!("Invalid rewind point: {}", n))?;
+                    let reverted = journal.rewind(n)?;
+                    if let Some(len) = reverted.messages {
+                        self.agent.truncate_history(len);
+                    }
+                    print_reverted(&reverted);
+                    println!("Conversation rewound to point {}.", n);
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
 
-            // If there are tool calls, execute them
-            if let Some(tool_calls) = response.tool_calls {
-                tracing::info!("LLM requested {} tool calls", tool_calls.len());
+        if name == "prompts" {
+            if self.prompts.is_empty() {
+                println!("No prompts found in ~/.config/aishell/prompts or .aishell/prompts");
+            }
+            for prompt in self.prompts.iter() {
+                let args: Vec<String> = prompt
+                    .arguments
+                    .iter()
+                    .map(|a| match a.required {
+                        true => format!("<{}>", a.name),
+                        false => format!("[{}]", a.name),
+                    })
+                    .collect();
+                println!(
+                    "/{} {} - {}",
+                    prompt.name,
+                    args.join(" "),
+                    prompt.description.as_deref().unwrap_or("")
+                );
+            }
+            return Ok(());
+        }
 
-                // Add assistant message with tool calls
-                let mut assistant_msg = Message::assistant(response.content.clone());
-                assistant_msg.tool_calls = Some(tool_calls.clone());
-                self.messages.push(assistant_msg);
+        let prompt = self
+            .prompts
+            .get(name)
+            .with_context(|| format!("Unknown command: /{}", name))?;
+        let input = prompt.render(&prompt.bind_args(args)?)?;
 
-                // Execute each tool call
-                for tool_call in tool_calls {
-                    let tool_name = &tool_call.function.name;
-                    let tool_args = &tool_call.function.arguments;
+        self.process_input(&input).await
+    }
 
-                    println!("\n[Executing tool: {}]", tool_name);
+    async fn process_input(&mut self, input: &str) -> Result<()> {
+        let messages = self.agent.messages().len();
+        if let Some(journal) = self.agent.executor().journal() {
+            journal.begin_turn(input, messages);
+        }
+        let mut observer = TerminalObserver {
+            settings: self.settings.clone(),
+            live: None,
+            prompting: Arc::new(AtomicBool::new(false)),
+        };
 
-                    let result = match execute_tool(tool_name, tool_args, &self.executor) {
-                        Ok(output) => output,
-                        Err(e) => format!("Error executing tool: {}", e),
-                    };
+        // Commands run in their own process group, so Ctrl-C only reaches
+        // aishell; dropping the run kills whatever command it started. An
+        // open approval prompt handles Ctrl-C itself by declining the call.
+        let prompting = observer.prompting.clone();
+        let ctrl_c = async move {
+            loop {
+                if tokio::signal::ctrl_c().await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+                if !prompting.load(Ordering::SeqCst) {
+                    break;
+                }
+            }
+        };
+        let interrupted = tokio::select! {
+            run = self.agent.run(input, &mut observer) => {
+                run?;
+                false
+            }
+            () = ctrl_c => true,
+        };
+        if interrupted {
+            if let Some(live) = observer.live.take() {
+                live.finish();
+            }
+            // A tool call without its result would be rejected by the model
+            self.agent.truncate_history(messages);
+            println!("\nInterrupted.");
+        }
+        Ok(())
+    }
+}
 
-                    println!("{}", result);
+/// Report which files were restored and which were left alone
+fn print_reverted(reverted: &Reverted) {
+    for path in &reverted.restored {
+        println!("Restored {}", path.display());
+    }
+    for path in &reverted.conflicts {
+        println!(
+            "Skipped {}: it was changed after the agent wrote it; its recorded changes were discarded",
+            path.display()
+        );
+    }
+    if reverted.restored.is_empty() && reverted.conflicts.is_empty() {
+        println!("No file changes to revert.");
+    }
+}
 
-                    // Add tool result message
-                    self.messages.push(Message::tool(result, tool_call.id.clone()));
+/// Prints tool activity and answers to the terminal
+struct TerminalObserver {
+    settings: ReplConfig,
+    /// The view of the bash command currently running
+    live: Option<LiveOutput>,
+    /// Set while asking for approval, so Ctrl-C doesn't abandon the prompt
+    /// with its read still pending
+    prompting: Arc<AtomicBool>,
+}
+
+impl TerminalObserver {
+    /// Read answers until one is valid; Ctrl-C or end of input declines
+    async fn read_approval(&self) -> Approval {
+        loop {
+            let line = tokio::task::spawn_blocking(|| DefaultEditor::new()?.readline("> ")).await;
+            let answer = match line {
+                Ok(Ok(line)) => line.trim().to_lowercase(),
+                Ok(Err(ReadlineError::Interrupted)) => {
+                    println!("^C");
+                    return Approval::Deny;
                 }
+                // No terminal to ask on, e.g. piped input to `aishell exec`
+                _ => return Approval::Deny,
+            };
 
-                // Continue the loop to get the next response
-                continue;
+            match answer.as_str() {
+                "y" | "yes" => return Approval::Once,
+                "n" | "no" => return Approval::Deny,
+                "a" | "always" => return Approval::Session,
+                "s" | "save" => return Approval::Persist,
+                _ => println!("Please answer y, n, a or s"),
             }
+        }
+    }
+}
 
-            // No tool calls, so the LLM is done
-            if !response.content.is_empty() {
-                println!("\n{}\n", response.content);
-                self.messages.push(Message::assistant(response.content));
-            }
+#[async_trait]
+impl AgentObserver for TerminalObserver {
+    async fn approve(&mut self, request: &ApprovalRequest) -> Approval {
+        match request.subject.as_str() {
+            "" => println!("\n[Permission required: {}]", request.tool),
+            subject => println!("\n[Permission required: {}] {}", request.tool, subject),
+        }
+        if let Some(reason) = &request.reason {
+            println!("Warning: {}", reason);
+        }
+        println!(
+            "Allow? [y]es / [n]o / [a]lways this session / always and [s]ave ({})",
+            request.rules.join(", ")
+        );
+
+        self.prompting.store(true, Ordering::SeqCst);
+        let approval = self.read_approval().await;
+        self.prompting.store(false, Ordering::SeqCst);
+        approval
+    }
 
-            break;
+    fn on_tool_call(&mut self, name: &str, _arguments: &str) {
+        println!("\n[Executing tool: {}]", name);
+        if name == "bash" {
+            self.live = Some(LiveOutput::new(
+                self.settings.output_view,
+                self.settings.output_lines,
+            ));
         }
+    }
 
-        Ok(())
+    fn on_tool_output(&mut self, _name: &str, chunk: &str) {
+        if let Some(live) = &mut self.live {
+            live.push(chunk);
+        }
+    }
+
+    fn on_tool_result(&mut self, _name: &str, result: &str) {
+        match self.live.take() {
+            // The output was already shown; only report how the command ended
+            Some(live) => {
+                live.finish();
+                println!("{}", result.lines().next().unwrap_or_default());
+            }
+            None => println!("{}", result),
+        }
+    }
+
+    fn on_response(&mut self, content: &str) {
+        println!("\n{}\n", content);
     }
 }
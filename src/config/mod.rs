@@ -1,53 +1,294 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub llm: LLMConfig,
     pub shell: ShellConfig,
+    pub server: ServerConfig,
+    pub repl: ReplConfig,
+    pub permissions: PermissionsConfig,
+    pub sandbox: SandboxConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct LLMConfig {
     pub default_provider: String,
     pub openai: OpenAIConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct OpenAIConfig {
     pub model: String,
     pub base_url: Option<String>,
 }
 
+/// The `interpreter` setting: a shell name or path, or a full argv that
+/// the command is appended to (e.g. `["nu", "-c"]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum InterpreterConfig {
+    Program(String),
+    Argv(Vec<String>),
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ShellConfig {
     pub max_execution_time: u64,
     pub workdir: Option<PathBuf>,
+    /// Shell for the bash tool; bash when installed, sh otherwise
+    pub interpreter: Option<InterpreterConfig>,
+    /// Start the shell as a login shell so it reads the profile
+    pub login: bool,
+    /// File sourced before commands run, e.g. for aliases and environment
+    pub rc_file: Option<PathBuf>,
+    /// Tool results with more lines are cut down to their head and tail (0 = no limit)
+    pub max_output_lines: usize,
+    /// Tool results with more bytes are cut down to their head and tail (0 = no limit)
+    pub max_output_bytes: usize,
+    /// Keep one shell running so `cd`, `export` and functions persist between commands
+    pub persistent: bool,
+    /// Directories the file tools may use besides the workdir (or MCP roots)
+    pub allowed_dirs: Vec<PathBuf>,
+    /// Files the file tools refuse to touch: a name pattern like `.env` or
+    /// `*.pem`, or a path like `~/.ssh` covering everything below it
+    pub sensitive_paths: Vec<String>,
+    /// Exceptions to `sensitive_paths`, in the same form
+    pub allowed_sensitive_paths: Vec<String>,
+}
+
+/// How command output is shown while a bash tool call runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputView {
+    /// Print every line as it arrives
+    Full,
+    /// Show a scrolling window of the latest lines, collapsed when done
+    #[default]
+    Tail,
+    /// Print nothing until the command finishes
+    Off,
+}
+
+/// Terminal behavior of the interactive shell
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplConfig {
+    pub output_view: OutputView,
+    /// Height of the `tail` view in lines
+    pub output_lines: usize,
+}
+
+/// What happens to a tool call that no permission rule matches
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PermissionMode {
+    /// Ask the user before running it
+    #[default]
+    Ask,
+    /// Run it
+    Auto,
+    /// Refuse it
+    Deny,
+}
+
+/// Rules for which tool calls may run, e.g. `bash(git status:*)`,
+/// `bash(cargo *)` or `write(src/**)`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PermissionsConfig {
+    pub mode: PermissionMode,
+    pub allow: Vec<String>,
+    /// Calls that always need confirmation, even when an allow rule or
+    /// `auto` mode would let them run
+    pub ask: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+/// Isolation for commands run by the bash tool (Linux only)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    /// `off`, `workspace`, `network`, or the name of an entry in `profiles`
+    pub profile: String,
+    pub profiles: HashMap<String, SandboxProfileConfig>,
+}
+
+/// A sandbox profile. Commands can always write to the workdir and temp
+/// directories and read everything else that isn't hidden.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SandboxProfileConfig {
+    pub network: bool,
+    /// More directories commands may write to
+    pub writable: Vec<PathBuf>,
+    /// Files and directories that appear empty inside the sandbox; `~` is
+    /// the home directory
+    pub hidden: Vec<PathBuf>,
+}
+
+/// Restrictions applied by `aishell server`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Tools exposed to MCP clients; all tools when unset
+    pub allowed_tools: Option<Vec<String>>,
+    /// Only expose tools that cannot modify the system
+    pub read_only: bool,
+    /// Directories file tools are confined to; the client's roots when empty
+    pub roots: Vec<PathBuf>,
+    /// Offer the `aishell_agent` tool to clients
+    pub agent: bool,
+    /// Provider for the agent tool ("sampling" borrows the client's model).
+    /// When unset, sampling is used if the client supports it and
+    /// `llm.default_provider` otherwise.
+    pub agent_provider: Option<String>,
+    /// Model for the agent tool; the provider's default when unset
+    pub agent_model: Option<String>,
+    /// What happens to calls no permission rule matches, in place of
+    /// `permissions.mode`. `auto` when unset; `ask` confirms them through
+    /// elicitation and refuses them for clients without it.
+    pub permission_mode: Option<PermissionMode>,
+}
+
+impl Default for LLMConfig {
+    fn default() -> Self {
+        Self {
+            default_provider: "openai".to_string(),
+            openai: OpenAIConfig::default(),
+        }
+    }
+}
+
+impl Default for OpenAIConfig {
+    fn default() -> Self {
+        Self {
+            model: "gpt-4".to_string(),
+            base_url: None,
+        }
+    }
+}
+
+impl Default for ShellConfig {
+    fn default() -> Self {
+        Self {
+            max_execution_time: 300,
+            workdir: None,
+            interpreter: None,
+            login: false,
+            rc_file: None,
+            max_output_lines: 500,
+            max_output_bytes: 30_000,
+            persistent: false,
+            allowed_dirs: Vec::new(),
+            sensitive_paths: [
+                ".env",
+                ".env.*",
+                "*.pem",
+                "*.key",
+                "id_rsa*",
+                "id_ecdsa*",
+                "id_ed25519*",
+                ".netrc",
+                ".git-credentials",
+                "~/.ssh",
+                "~/.gnupg",
+                "~/.aws",
+                "~/.kube",
+                "~/.docker/config.json",
+                "~/.config/gh",
+                "~/.config/aishell",
+                "**/.aishell/config.toml",
+            ]
+            .map(String::from)
+            .to_vec(),
+            allowed_sensitive_paths: [".env.example", ".env.sample", ".env.template"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+impl Default for ReplConfig {
+    fn default() -> Self {
+        Self {
+            output_view: OutputView::default(),
+            output_lines: 10,
+        }
+    }
 }
 
-impl Default for Config {
+impl Default for PermissionsConfig {
     fn default() -> Self {
         Self {
-            llm: LLMConfig {
-                default_provider: "openai".to_string(),
-                openai: OpenAIConfig {
-                    model: "gpt-4".to_string(),
-                    base_url: None,
-                },
-            },
-            shell: ShellConfig {
-                max_execution_time: 300,
-                workdir: None,
-            },
+            mode: PermissionMode::default(),
+            allow: vec!["read".to_string(), "list".to_string(), "search".to_string()],
+            ask: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            profile: "off".to_string(),
+            profiles: HashMap::new(),
+        }
+    }
+}
+
+impl Default for SandboxProfileConfig {
+    fn default() -> Self {
+        let hidden = [
+            "~/.ssh",
+            "~/.gnupg",
+            "~/.aws",
+            "~/.azure",
+            "~/.kube",
+            "~/.docker",
+            "~/.config/gcloud",
+            "~/.config/gh",
+            "~/.config/aishell",
+            "~/.password-store",
+            "~/.local/share/keyrings",
+            "~/.netrc",
+            "~/.git-credentials",
+            "~/.npmrc",
+            "~/.pypirc",
+            "~/.cargo/credentials.toml",
+        ];
+        Self {
+            network: false,
+            writable: Vec::new(),
+            hidden: hidden.iter().map(PathBuf::from).collect(),
         }
     }
 }
 
 impl Config {
+    /// Load `~/.config/aishell/config.toml`, falling back to defaults
     pub fn load() -> Result<Self> {
-        // For now, just return default config
-        // TODO: Load from file in ~/.config/aishell/config.toml
-        Ok(Self::default())
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config: {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse config: {}", path.display()))
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("aishell").join("config.toml"))
     }
 }
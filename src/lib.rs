@@ -1,7 +1,10 @@
+pub mod agent;
 pub mod cli;
 pub mod config;
 pub mod llm;
 pub mod mcp;
+pub mod permissions;
+pub mod prompts;
 pub mod shell;
 
 pub use config::Config;
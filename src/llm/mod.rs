@@ -13,6 +13,9 @@ pub async fn create_provider(provider: &str, model: Option<&str>) -> Result<Box<
             let provider = OpenAIProvider::new(model)?;
             Ok(Box::new(provider))
         }
+        "sampling" => anyhow::bail!(
+            "The sampling provider is only available to aishell_agent when running as an MCP server"
+        ),
         _ => anyhow::bail!("Unsupported provider: {}", provider),
     }
 }
@@ -1,9 +1,16 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-use tracing_subscriber;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing_subscriber::prelude::*;
 
 use aishell::cli::Repl;
-use aishell::mcp::MCPServer;
+use aishell::mcp::{AgentSettings, MCPServer, McpLogLayer, ServerPolicy};
+use aishell::permissions::Permissions;
+use aishell::shell::{Sandbox, ShellExecutor};
+use aishell::config::PermissionMode;
+use aishell::Config;
 
 #[derive(Parser)]
 #[command(name = "aishell")]
@@ -25,6 +32,10 @@ enum Commands {
         /// Model name
         #[arg(short, long)]
         model: Option<String>,
+
+        /// Run commands in this sandbox profile (off, workspace, network, or a custom one)
+        #[arg(long, value_name = "PROFILE")]
+        sandbox: Option<String>,
     },
 
     /// Execute a single command via AI
@@ -35,38 +46,126 @@ enum Commands {
         /// LLM provider
         #[arg(short = 'p', long, default_value = "openai")]
         provider: String,
+
+        /// Run commands in this sandbox profile (off, workspace, network, or a custom one)
+        #[arg(long, value_name = "PROFILE")]
+        sandbox: Option<String>,
     },
 
     /// Start MCP server (for Claude Desktop integration)
-    Server,
+    Server {
+        /// Serve the streamable HTTP transport on this address instead of stdio
+        #[arg(long, value_name = "ADDR")]
+        http: Option<SocketAddr>,
+
+        /// Additional browser origin allowed to connect over HTTP (repeatable)
+        #[arg(long = "allow-origin", value_name = "ORIGIN")]
+        allowed_origins: Vec<String>,
+
+        /// Only expose this tool to clients (repeatable)
+        #[arg(long = "allow-tool", value_name = "TOOL")]
+        allowed_tools: Vec<String>,
+
+        /// Only expose tools that cannot modify the system
+        #[arg(long)]
+        read_only: bool,
+
+        /// Confine file tools to this directory instead of the client's roots (repeatable)
+        #[arg(long = "root", value_name = "DIR")]
+        roots: Vec<PathBuf>,
+
+        /// Offer the aishell_agent tool, which runs the full agent loop
+        #[arg(long)]
+        agent: bool,
+
+        /// Run commands in this sandbox profile (off, workspace, network, or a custom one)
+        #[arg(long, value_name = "PROFILE")]
+        sandbox: Option<String>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive(tracing::Level::INFO.into()),
+    // Initialize logging (stderr, so stdout stays free for the MCP protocol).
+    // MCP clients pick their own verbosity, so that layer is not env-filtered.
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_filter(
+                    tracing_subscriber::EnvFilter::from_default_env()
+                        .add_directive(tracing::Level::INFO.into()),
+                ),
         )
+        .with(McpLogLayer)
         .init();
 
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Shell { provider, model } => {
-            let mut repl = Repl::new(&provider, model.as_deref()).await?;
+        Commands::Shell {
+            provider,
+            model,
+            sandbox,
+        } => {
+            let mut repl = Repl::new(&provider, model.as_deref(), sandbox.as_deref()).await?;
             repl.run().await?;
         }
 
-        Commands::Exec { prompt, provider } => {
-            let mut repl = Repl::new(&provider, None).await?;
+        Commands::Exec {
+            prompt,
+            provider,
+            sandbox,
+        } => {
+            let mut repl = Repl::new(&provider, None, sandbox.as_deref()).await?;
             repl.execute_once(&prompt).await?;
         }
 
-        Commands::Server => {
-            let server = MCPServer::new()?;
-            server.run().await?;
+        Commands::Server {
+            http,
+            allowed_origins,
+            allowed_tools,
+            read_only,
+            roots,
+            agent,
+            sandbox,
+        } => {
+            let mut config = Config::load()?;
+            if let Some(profile) = sandbox {
+                config.sandbox.profile = profile;
+            }
+            let mut policy = ServerPolicy::from_config(&config.server)?;
+            if !allowed_tools.is_empty() {
+                policy.allowed_tools = Some(allowed_tools.into_iter().collect());
+            }
+            policy.read_only |= read_only;
+            if !roots.is_empty() {
+                policy = policy.with_roots(roots)?;
+            }
+
+            let executor = ShellExecutor::from_config(&config.shell)?
+                .with_sandbox(Sandbox::from_config(&config.sandbox)?);
+            // Not every client can confirm a call, so only ask when configured to
+            config.permissions.mode = config.server.permission_mode.unwrap_or(PermissionMode::Auto);
+            let permissions = Permissions::load(&config.permissions, executor.workdir())?;
+            let mut server = MCPServer::new(executor, policy)?.with_permissions(permissions);
+            if agent || config.server.agent {
+                server = server.with_agent(AgentSettings {
+                    provider: config
+                        .server
+                        .agent_provider
+                        .clone()
+                        .unwrap_or_else(|| config.llm.default_provider.clone()),
+                    model: config.server.agent_model.clone(),
+                    prefer_sampling: config.server.agent_provider.is_none(),
+                });
+            }
+
+            let server = Arc::new(server);
+            match http {
+                Some(addr) => server.serve_http(addr, allowed_origins).await?,
+                None => server.run().await?,
+            }
         }
     }
 
@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::agent::{Agent, AgentObserver};
+use crate::llm::provider::FunctionDefinition;
+use crate::llm::{create_provider, LLMProvider, ToolDefinition};
+use crate::permissions::{Approval, ApprovalRequest};
+use crate::shell::{get_tool_definitions, ShellExecutor};
+
+use super::sampling::SamplingProvider;
+use super::{MCPServer, Session};
+
+pub const AGENT_TOOL: &str = "aishell_agent";
+
+/// Provider used by the `aishell_agent` tool
+#[derive(Debug, Clone)]
+pub struct AgentSettings {
+    /// Provider name, or "sampling" to always borrow the client's model
+    pub provider: String,
+    pub model: Option<String>,
+    /// Borrow the client's model whenever it supports sampling
+    pub prefer_sampling: bool,
+}
+
+pub fn agent_tool_definition() -> ToolDefinition {
+    ToolDefinition {
+        tool_type: "function".to_string(),
+        function: FunctionDefinition {
+            name: AGENT_TOOL.to_string(),
+            description: "Delegate a whole task to aishell's own agent, which plans and runs shell and file tools with its configured model. Returns the agent's final answer and a summary of the tools it called.".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "task": {
+                        "type": "string",
+                        "description": "A self-contained description of the task to accomplish"
+                    }
+                },
+                "required": ["task"]
+            }),
+        },
+    }
+}
+
+/// Reports each tool the agent calls as an MCP progress notification
+struct ProgressObserver<'a> {
+    session: &'a Session,
+    progress_token: Value,
+    steps: u64,
+}
+
+#[async_trait]
+impl AgentObserver for ProgressObserver<'_> {
+    /// The client never sees the agent's own tool calls, so without
+    /// elicitation there is no one to ask and the call is refused
+    async fn approve(&mut self, request: &ApprovalRequest) -> Approval {
+        if !self.session.supports("elicitation") {
+            return Approval::Deny;
+        }
+        self.session.ask_approval(request).await
+    }
+
+    fn on_tool_call(&mut self, name: &str, arguments: &str) {
+        self.steps += 1;
+        if self.progress_token.is_null() {
+            return;
+        }
+
+        self.session.peer.notify(
+            "notifications/progress",
+            json!({
+                "progressToken": self.progress_token,
+                "progress": self.steps,
+                "message": format!("{} {}", name, arguments)
+            }),
+        );
+    }
+}
+
+impl MCPServer {
+    pub(super) async fn call_agent(
+        &self,
+        session: &Session,
+        executor: ShellExecutor,
+        params: &Value,
+        cancel: &CancellationToken,
+    ) -> Result<String> {
+        let settings = self
+            .agent
+            .as_ref()
+            .context("The aishell_agent tool is not enabled on this server")?;
+        let task = params["arguments"]["task"]
+            .as_str()
+            .context("Missing 'task' argument")?;
+
+        let use_sampling =
+            settings.provider == "sampling" || (settings.prefer_sampling && session.supports("sampling"));
+        let llm: Box<dyn LLMProvider> = if use_sampling {
+            if !session.supports("sampling") {
+                anyhow::bail!("The agent is configured to use sampling, but the client does not support it");
+            }
+            let supports_tools = session.client_capabilities.lock().unwrap()["sampling"]
+                .get("tools")
+                .is_some();
+            Box::new(SamplingProvider::new(
+                session.peer.clone(),
+                supports_tools,
+                settings.model.clone(),
+            ))
+        } else {
+            create_provider(&settings.provider, settings.model.as_deref()).await?
+        };
+        tracing::info!("Running agent with {} on task: {}", llm.model_name(), task);
+
+        // The agent may only use the tools this server exposes directly
+        let tools = get_tool_definitions(&executor)
+            .into_iter()
+            .filter(|t| self.policy.check_tool(&t.function.name).is_ok())
+            .collect();
+        let mut agent = Agent::new(llm, executor)
+            .with_tools(tools)
+            .with_permissions(self.permissions_for(session));
+        let mut observer = ProgressObserver {
+            session,
+            progress_token: params["_meta"]["progressToken"].clone(),
+            steps: 0,
+        };
+
+        let run = tokio::select! {
+            run = agent.run(task, &mut observer) => run?,
+            _ = cancel.cancelled() => anyhow::bail!("Agent cancelled"),
+        };
+
+        let answer = if run.exhausted {
+            "The agent stopped at its iteration limit before giving a final answer.".to_string()
+        } else {
+            run.answer.clone()
+        };
+
+        Ok(format!(
+            "{}\n\n---\nTranscript ({} tool calls):\n{}",
+            answer,
+            run.steps.len(),
+            run.transcript_summary()
+        ))
+    }
+}
@@ -0,0 +1,359 @@
+use anyhow::{Context, Result};
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use futures::stream;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use url::Url;
+
+use super::{MCPServer, Peer, Session};
+
+const SESSION_HEADER: &str = "mcp-session-id";
+
+/// Messages buffered for a session with no stream attached before newer
+/// ones are dropped
+const OUTGOING_CAPACITY: usize = 1024;
+
+/// Sessions with no requests or open stream for this long are closed
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// How often idle sessions are looked for
+const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A session plus the receiving end of its server-to-client messages,
+/// drained by whichever GET stream is currently attached
+struct HttpSession {
+    session: Arc<Session>,
+    outgoing: Arc<tokio::sync::Mutex<mpsc::Receiver<Value>>>,
+    /// When the client last sent a request or had a stream open
+    last_seen: Mutex<Instant>,
+}
+
+impl HttpSession {
+    fn touch(&self) {
+        *self.last_seen.lock().unwrap() = Instant::now();
+    }
+
+    /// Whether the client has been gone long enough to close the session
+    fn is_idle(&self) -> bool {
+        self.last_seen.lock().unwrap().elapsed() > SESSION_IDLE_TIMEOUT
+            && self.outgoing.try_lock().is_ok()
+            && self.session.in_flight.lock().unwrap().is_empty()
+    }
+
+    /// Stop the session's watcher and shell
+    async fn close(&self) {
+        self.session.watcher.lock().unwrap().take();
+        self.session.shell.reset().await;
+    }
+}
+
+/// Held by an open stream; the session's idle time counts from when it closes
+struct StreamGuard(Arc<HttpSession>);
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.0.touch();
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    server: Arc<MCPServer>,
+    sessions: Arc<Mutex<HashMap<String, Arc<HttpSession>>>>,
+    allowed_origins: Arc<Vec<String>>,
+}
+
+impl MCPServer {
+    /// Serve MCP over the streamable HTTP transport at `/mcp`
+    pub async fn serve_http(
+        self: Arc<Self>,
+        addr: SocketAddr,
+        allowed_origins: Vec<String>,
+    ) -> Result<()> {
+        if !addr.ip().is_loopback() {
+            tracing::warn!(
+                "MCP HTTP server is listening on non-loopback address {}; any host that can reach it can run commands",
+                addr
+            );
+        }
+
+        let state = AppState {
+            server: self,
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            allowed_origins: Arc::new(allowed_origins),
+        };
+        tokio::spawn(evict_idle_sessions(state.sessions.clone()));
+
+        let app = Router::new()
+            .route(
+                "/mcp",
+                post(handle_post).get(handle_get).delete(handle_delete),
+            )
+            .with_state(state);
+
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to bind {}", addr))?;
+        tracing::info!("Starting MCP server on http://{}/mcp", addr);
+
+        axum::serve(listener, app)
+            .await
+            .context("MCP HTTP server failed")?;
+
+        Ok(())
+    }
+}
+
+async fn handle_post(State(state): State<AppState>, headers: HeaderMap, body: Bytes) -> Response {
+    if let Err(e) = check_origin(&state, &headers) {
+        return e.into_response();
+    }
+
+    let payload: Value = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            return HttpError::new(
+                StatusCode::BAD_REQUEST,
+                -32700,
+                format!("Parse error: {}", e),
+            )
+            .into_response();
+        }
+    };
+
+    let (messages, batch) = match payload {
+        Value::Array(messages) => (messages, true),
+        message => (vec![message], false),
+    };
+
+    let initializing = messages.iter().any(|m| m["method"] == "initialize");
+    let (session_id, http_session) = if initializing {
+        let (tx, rx) = mpsc::channel(OUTGOING_CAPACITY);
+        let session_id = uuid::Uuid::new_v4().to_string();
+        let http_session = Arc::new(HttpSession {
+            session: Arc::new(Session::new(Peer::bounded(tx))),
+            outgoing: Arc::new(tokio::sync::Mutex::new(rx)),
+            last_seen: Mutex::new(Instant::now()),
+        });
+        state
+            .sessions
+            .lock()
+            .unwrap()
+            .insert(session_id.clone(), http_session.clone());
+        tracing::info!("Created MCP HTTP session {}", session_id);
+        (session_id, http_session)
+    } else {
+        match find_session(&state, &headers) {
+            Ok(found) => found,
+            Err(e) => return e.into_response(),
+        }
+    };
+
+    http_session.touch();
+    let mut responses = Vec::new();
+    for message in &messages {
+        if let Some(response) = state
+            .server
+            .handle_message(&http_session.session, message)
+            .await
+        {
+            responses.push(response);
+        }
+    }
+
+    let mut response = if responses.is_empty() {
+        StatusCode::ACCEPTED.into_response()
+    } else if batch {
+        Json(Value::Array(responses)).into_response()
+    } else {
+        Json(responses.remove(0)).into_response()
+    };
+
+    if let Ok(value) = HeaderValue::from_str(&session_id) {
+        response.headers_mut().insert(SESSION_HEADER, value);
+    }
+
+    response
+}
+
+/// Open the SSE stream carrying notifications for a session
+async fn handle_get(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(e) = check_origin(&state, &headers) {
+        return e.into_response();
+    }
+
+    let accepts_sse = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.contains("text/event-stream"))
+        .unwrap_or(false);
+    if !accepts_sse {
+        return StatusCode::NOT_ACCEPTABLE.into_response();
+    }
+
+    let (session_id, http_session) = match find_session(&state, &headers) {
+        Ok(found) => found,
+        Err(e) => return e.into_response(),
+    };
+
+    // Only one stream may drain a session at a time; the guard is released
+    // when the client disconnects so it can reconnect later
+    let Ok(outgoing) = http_session.outgoing.clone().try_lock_owned() else {
+        return HttpError::new(
+            StatusCode::CONFLICT,
+            -32600,
+            format!("Session {} already has an open stream", session_id),
+        )
+        .into_response();
+    };
+
+    let attached = StreamGuard(http_session);
+    let events = stream::unfold((outgoing, attached), |(mut outgoing, attached)| async move {
+        let message = outgoing.recv().await?;
+        let event = Event::default().event("message").data(message.to_string());
+        Some((Ok::<_, Infallible>(event), (outgoing, attached)))
+    });
+
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+async fn handle_delete(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(e) = check_origin(&state, &headers) {
+        return e.into_response();
+    }
+
+    let (session_id, http_session) = match find_session(&state, &headers) {
+        Ok(found) => found,
+        Err(e) => return e.into_response(),
+    };
+
+    state.sessions.lock().unwrap().remove(&session_id);
+    http_session.close().await;
+    tracing::info!("Closed MCP HTTP session {}", session_id);
+
+    StatusCode::OK.into_response()
+}
+
+/// Close sessions whose clients went away without deleting them
+async fn evict_idle_sessions(sessions: Arc<Mutex<HashMap<String, Arc<HttpSession>>>>) {
+    let mut interval = tokio::time::interval(EVICTION_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let idle: Vec<(String, Arc<HttpSession>)> = {
+            let mut sessions = sessions.lock().unwrap();
+            let ids: Vec<String> = sessions
+                .iter()
+                .filter(|(_, s)| s.is_idle())
+                .map(|(id, _)| id.clone())
+                .collect();
+            ids.into_iter()
+                .filter_map(|id| sessions.remove(&id).map(|s| (id, s)))
+                .collect()
+        };
+
+        for (session_id, http_session) in idle {
+            http_session.close().await;
+            tracing::info!("Closed idle MCP HTTP session {}", session_id);
+        }
+    }
+}
+
+fn find_session(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<(String, Arc<HttpSession>), HttpError> {
+    let Some(session_id) = headers.get(SESSION_HEADER).and_then(|v| v.to_str().ok()) else {
+        return Err(HttpError::new(
+            StatusCode::BAD_REQUEST,
+            -32600,
+            "Missing Mcp-Session-Id header",
+        ));
+    };
+
+    match state.sessions.lock().unwrap().get(session_id) {
+        Some(http_session) => Ok((session_id.to_string(), http_session.clone())),
+        None => Err(HttpError::new(
+            StatusCode::NOT_FOUND,
+            -32600,
+            format!("Unknown session: {}", session_id),
+        )),
+    }
+}
+
+/// Reject cross-origin browser requests to guard against DNS rebinding
+fn check_origin(state: &AppState, headers: &HeaderMap) -> Result<(), HttpError> {
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return Ok(());
+    };
+    let origin = origin.to_str().unwrap_or_default();
+
+    if state
+        .allowed_origins
+        .iter()
+        .any(|allowed| allowed == origin)
+    {
+        return Ok(());
+    }
+
+    let is_local = Url::parse(origin)
+        .ok()
+        .and_then(|url| url.host_str().map(|h| h.to_string()))
+        .map(|host| matches!(host.as_str(), "localhost" | "127.0.0.1" | "[::1]"))
+        .unwrap_or(false);
+    if is_local {
+        return Ok(());
+    }
+
+    tracing::warn!("Rejected MCP HTTP request from origin {}", origin);
+    Err(HttpError::new(
+        StatusCode::FORBIDDEN,
+        -32600,
+        format!("Origin not allowed: {}", origin),
+    ))
+}
+
+/// A transport-level failure, reported as a JSON-RPC error with no id
+struct HttpError {
+    status: StatusCode,
+    code: i64,
+    message: String,
+}
+
+impl HttpError {
+    fn new(status: StatusCode, code: i64, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl IntoResponse for HttpError {
+    fn into_response(self) -> Response {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": null,
+            "error": {
+                "code": self.code,
+                "message": self.message
+            }
+        });
+
+        (self.status, Json(body)).into_response()
+    }
+}
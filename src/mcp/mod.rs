@@ -1,25 +1,225 @@
+mod agent;
+mod http;
+mod logging;
+mod policy;
+mod resources;
+mod sampling;
+mod session;
+
+pub use agent::AgentSettings;
+pub use logging::{LogLevel, McpLogLayer};
+pub use policy::ServerPolicy;
+pub use sampling::SamplingProvider;
+pub use session::{Peer, Session};
+
 use anyhow::Result;
-use serde_json::json;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::prompts::PromptLibrary;
+use crate::permissions::{Decision, Permissions};
+use crate::shell::{
+    bash_tool_result, execute_bash_streaming, execute_search, execute_tool,
+    execution_result_schema, get_tool_definitions, search_results_schema, ShellExecutor,
+};
+use resources::ResourceProvider;
+
+/// Protocol revisions this server speaks, newest first
+const PROTOCOL_VERSIONS: &[&str] = &["2025-11-25", "2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// A JSON-RPC error sent back in place of a result
+#[derive(Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn method_not_found(method: &str) -> Self {
+        Self {
+            code: -32601,
+            message: format!("Method not found: {}", method),
+        }
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        Self {
+            code: -32602,
+            message: message.into(),
+        }
+    }
 
-use crate::shell::{execute_tool, get_tool_definitions, ShellExecutor};
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self {
+            code: -32603,
+            message: message.into(),
+        }
+    }
+
+    pub fn resource_not_found(message: impl Into<String>) -> Self {
+        Self {
+            code: -32002,
+            message: message.into(),
+        }
+    }
+}
 
 pub struct MCPServer {
     executor: ShellExecutor,
+    resources: ResourceProvider,
+    prompts: PromptLibrary,
+    policy: ServerPolicy,
+    permissions: Permissions,
+    agent: Option<AgentSettings>,
 }
 
 impl MCPServer {
-    pub fn new() -> Result<Self> {
+    pub fn new(executor: ShellExecutor, policy: ServerPolicy) -> Result<Self> {
+        let executor = executor.with_roots(policy.roots.clone());
+        let resources = ResourceProvider::new(&executor)?;
+        let prompts = PromptLibrary::load(executor.workdir())?;
+
         Ok(Self {
-            executor: ShellExecutor::default(),
+            executor,
+            resources,
+            prompts,
+            policy,
+            permissions: Permissions::default(),
+            agent: None,
         })
     }
 
-    pub async fn run(&self) -> Result<()> {
+    /// Check tool calls against `permissions`. Calls the rules say to ask
+    /// about are confirmed through elicitation when the client supports it.
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Offer the `aishell_agent` tool, running the agent loop with this provider
+    pub fn with_agent(mut self, settings: AgentSettings) -> Self {
+        self.agent = Some(settings);
+        self
+    }
+
+    /// The executor for a session, confined to the server's explicit roots
+    /// or else to whatever roots the client has shared. Without any, no tool
+    /// is available, so commands don't run unconfined in the launch directory.
+    async fn executor_for(&self, session: &Session, tool_name: &str) -> Result<ShellExecutor, String> {
+        let executor = if !self.policy.roots.is_empty() {
+            self.executor.clone()
+        } else {
+            match session.client_roots().await {
+                Some(roots) if roots.is_empty() => {
+                    return Err(format!(
+                        "The client has not shared any local roots, so the {} tool is unavailable; start the server with --root to choose one",
+                        tool_name
+                    ));
+                }
+                Some(roots) => self.executor.clone().with_roots(roots),
+                None => self.executor.clone(),
+            }
+        };
+
+        // Each client gets its own shell rather than sharing the server's
+        Ok(match executor.persistent_shell() {
+            Some(_) => executor.with_persistent_shell(session.shell.clone()),
+            None => executor,
+        })
+    }
+
+    /// The executor whose roots and sensitive paths limit the session's
+    /// resources, or `None` when the client shared no roots
+    async fn resource_executor(&self, session: &Session) -> Option<ShellExecutor> {
+        self.executor_for(session, "read").await.ok()
+    }
+
+    /// The session's permissions, starting with no approvals of its own
+    fn permissions_for(&self, session: &Session) -> Permissions {
+        session
+            .permissions
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| self.permissions.fork())
+            .clone()
+    }
+
+    /// Check the policy and permissions for a call, returning the executor
+    /// to run it with or the reason it was refused
+    async fn prepare_call(
+        &self,
+        session: &Session,
+        tool_name: &str,
+        arguments: &Value,
+    ) -> Result<ShellExecutor, String> {
+        self.policy.check_tool(tool_name)?;
+        let executor = self.executor_for(session, tool_name).await?;
+
+        let permissions = self.permissions_for(session);
+        let base = executor.current_dir();
+        match permissions.check(tool_name, arguments, &base) {
+            Decision::Allow => {}
+            Decision::Deny(reason) => return Err(format!("Permission denied: {}", reason)),
+            Decision::Ask(requests) if !session.supports("elicitation") => {
+                let request = requests.iter().find(|r| r.reason.is_some()).unwrap_or(&requests[0]);
+                let subject = match request.reason.as_deref() {
+                    Some(reason) => format!("`{}` looks destructive ({}) and", request.subject, reason),
+                    None if request.subject.is_empty() => format!("the {} tool", request.tool),
+                    None => format!("`{}`", request.subject),
+                };
+                return Err(format!(
+                    "Permission denied: {} needs approval, which was unavailable because the client doesn't support elicitation; allow it with a [permissions] rule or set server.permission_mode = \"auto\"",
+                    subject
+                ));
+            }
+            Decision::Ask(requests) => {
+                for request in &requests {
+                    let approval = session.ask_approval(request).await;
+                    match permissions.resolve(request, approval) {
+                        Ok(true) => {}
+                        Ok(false) => return Err("Permission denied: the user declined this call".to_string()),
+                        Err(e) => {
+                            return Err(format!("Permission denied: approval was unavailable: {:#}", e));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(executor)
+    }
+
+    /// Watch the workspace for the session, unless it already is
+    fn start_watcher(&self, session: &Arc<Session>) -> Result<()> {
+        let mut watcher = session.watcher.lock().unwrap();
+        if watcher.is_none() {
+            *watcher = Some(self.resources.watch(Arc::downgrade(session))?);
+        }
+        Ok(())
+    }
+
+    pub async fn run(self: Arc<Self>) -> Result<()> {
         tracing::info!("Starting MCP server");
 
+        let (tx, mut rx) = mpsc::unbounded_channel::<Value>();
+        let session = Arc::new(Session::new(Peer::new(tx)));
+
+        let writer = tokio::spawn(async move {
+            let mut stdout = io::stdout();
+            while let Some(message) = rx.recv().await {
+                let message_str = serde_json::to_string(&message)?;
+                stdout.write_all(message_str.as_bytes()).await?;
+                stdout.write_all(b"\n").await?;
+                stdout.flush().await?;
+            }
+            Ok::<_, anyhow::Error>(())
+        });
+
         let stdin = io::stdin();
-        let mut stdout = io::stdout();
         let mut reader = BufReader::new(stdin);
         let mut line = String::new();
 
@@ -31,7 +231,11 @@ impl MCPServer {
                 break; // EOF
             }
 
-            let request: serde_json::Value = match serde_json::from_str(&line) {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let message: Value = match serde_json::from_str(&line) {
                 Ok(v) => v,
                 Err(e) => {
                     tracing::error!("Failed to parse request: {}", e);
@@ -39,63 +243,259 @@ impl MCPServer {
                 }
             };
 
-            let response = self.handle_request(&request).await;
-            let response_str = serde_json::to_string(&response)?;
-
-            stdout.write_all(response_str.as_bytes()).await?;
-            stdout.write_all(b"\n").await?;
-            stdout.flush().await?;
+            // Handle requests concurrently so a slow tool call doesn't block
+            // pings or cancellations arriving behind it
+            let server = self.clone();
+            let session = session.clone();
+            tokio::spawn(async move {
+                if let Some(response) = server.handle_message(&session, &message).await {
+                    session.peer.send(response);
+                }
+            });
         }
 
+        // Dropping the session closes the channel and stops the writer
+        session.watcher.lock().unwrap().take();
+        drop(session);
+        writer.await??;
+
         Ok(())
     }
 
-    async fn handle_request(&self, request: &serde_json::Value) -> serde_json::Value {
-        let method = request["method"].as_str().unwrap_or("");
+    /// Dispatch one incoming message, returning the response for requests
+    async fn handle_message(&self, session: &Arc<Session>, message: &Value) -> Option<Value> {
+        logging::scoped(session, self.dispatch(session, message)).await
+    }
+
+    async fn dispatch(&self, session: &Arc<Session>, message: &Value) -> Option<Value> {
+        let Some(method) = message["method"].as_str() else {
+            // A response to one of our own requests
+            if message.get("id").is_some() {
+                session.peer.resolve(message);
+            }
+            return None;
+        };
+        let params = &message["params"];
+
+        let Some(id) = message.get("id") else {
+            self.handle_notification(session, method, params);
+            return None;
+        };
+
+        let cancel = CancellationToken::new();
+        let key = id.to_string();
+        session
+            .in_flight
+            .lock()
+            .unwrap()
+            .insert(key.clone(), cancel.clone());
+
+        let result = self
+            .handle_request(session, method, params, &cancel)
+            .await;
+        session.in_flight.lock().unwrap().remove(&key);
+
+        // Cancelled requests get no response
+        if cancel.is_cancelled() {
+            tracing::info!("Request {} was cancelled", key);
+            return None;
+        }
+
+        let response = match result {
+            Ok(result) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "result": result
+            }),
+            Err(e) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {
+                    "code": e.code,
+                    "message": e.message
+                }
+            }),
+        };
+
+        Some(response)
+    }
+
+    fn handle_notification(&self, session: &Arc<Session>, method: &str, params: &Value) {
+        tracing::debug!("Received notification: {}", method);
+
+        if matches!(method, "notifications/initialized" | "notifications/roots/list_changed")
+            && self.policy.roots.is_empty()
+            && session.supports("roots")
+        {
+            // Tool calls wait for the answer rather than run unconfined
+            session.roots_pending.send_modify(|pending| *pending += 1);
+            let session = session.clone();
+            tokio::spawn(async move {
+                let refresh = async {
+                    if let Err(e) = session.refresh_roots().await {
+                        tracing::warn!("Failed to fetch client roots: {:#}", e);
+                        session.roots.lock().unwrap().get_or_insert_with(Vec::new);
+                    }
+                    session.roots_pending.send_modify(|pending| *pending -= 1);
+                };
+                logging::scoped(&session, refresh).await;
+            });
+        }
+
+        if method == "notifications/cancelled" {
+            let key = params["requestId"].to_string();
+            if let Some(cancel) = session.in_flight.lock().unwrap().get(&key) {
+                tracing::info!(
+                    "Cancelling request {}: {}",
+                    key,
+                    params["reason"].as_str().unwrap_or("no reason given")
+                );
+                cancel.cancel();
+            }
+        }
+    }
 
+    async fn handle_request(
+        &self,
+        session: &Arc<Session>,
+        method: &str,
+        params: &Value,
+        cancel: &CancellationToken,
+    ) -> Result<Value, RpcError> {
         match method {
             "initialize" => {
-                json!({
-                    "protocolVersion": "2024-11-05",
+                *session.client_capabilities.lock().unwrap() = params["capabilities"].clone();
+                logging::forward_to(session);
+                // Watch from the start so list changes reach the client even
+                // if it never subscribes to a resource
+                if let Err(e) = self.start_watcher(session) {
+                    tracing::warn!("{:#}", e);
+                }
+
+                Ok(json!({
+                    "protocolVersion": negotiate_version(params["protocolVersion"].as_str()),
                     "capabilities": {
-                        "tools": {}
+                        "tools": {},
+                        "resources": {
+                            "subscribe": true,
+                            "listChanged": true
+                        },
+                        "prompts": {},
+                        "logging": {}
                     },
                     "serverInfo": {
                         "name": "aishell",
                         "version": "0.1.0"
                     }
-                })
+                }))
+            }
+
+            "ping" => Ok(json!({})),
+
+            "logging/setLevel" => {
+                let level = params["level"]
+                    .as_str()
+                    .and_then(LogLevel::parse)
+                    .ok_or_else(|| RpcError::invalid_params("Invalid or missing 'level' parameter"))?;
+                *session.log_level.lock().unwrap() = level;
+                Ok(json!({}))
             }
 
             "tools/list" => {
-                let tools = get_tool_definitions();
+                let mut tools = get_tool_definitions(&self.executor);
+                if self.agent.is_some() {
+                    tools.push(agent::agent_tool_definition());
+                }
                 let tool_list: Vec<_> = tools
                     .iter()
+                    .filter(|t| self.policy.check_tool(&t.function.name).is_ok())
                     .map(|t| {
-                        json!({
+                        let mut tool = json!({
                             "name": t.function.name,
                             "description": t.function.description,
                             "inputSchema": t.function.parameters
-                        })
+                        });
+                        match t.function.name.as_str() {
+                            "bash" => tool["outputSchema"] = execution_result_schema(),
+                            "search" => tool["outputSchema"] = search_results_schema(),
+                            _ => {}
+                        }
+                        tool
                     })
                     .collect();
 
-                json!({
+                Ok(json!({
                     "tools": tool_list
-                })
+                }))
             }
 
             "tools/call" => {
-                let tool_name = request["params"]["name"].as_str().unwrap_or("");
-                let arguments = request["params"]["arguments"].to_string();
+                let tool_name = params["name"].as_str().unwrap_or("");
+                let arguments = params["arguments"].to_string();
 
-                let result = match execute_tool(tool_name, &arguments, &self.executor) {
-                    Ok(output) => json!({
-                        "content": [{
-                            "type": "text",
-                            "text": output
-                        }]
-                    }),
+                let executor = match self.prepare_call(session, tool_name, &params["arguments"]).await {
+                    Ok(executor) => executor,
+                    Err(message) => {
+                        tracing::warn!("Rejected call to {}: {}", tool_name, message);
+                        return Ok(json!({
+                            "content": [{
+                                "type": "text",
+                                "text": format!("Error: {}", message)
+                            }],
+                            "isError": true
+                        }));
+                    }
+                };
+
+                let output = match tool_name {
+                    "bash" => {
+                        let progress_token = params["_meta"]["progressToken"].clone();
+                        let mut progress = 0;
+                        let on_output = |chunk: &str| {
+                            if progress_token.is_null() {
+                                return;
+                            }
+                            progress += chunk.len();
+                            session.peer.notify(
+                                "notifications/progress",
+                                json!({
+                                    "progressToken": progress_token,
+                                    "progress": progress,
+                                    "message": chunk
+                                }),
+                            );
+                        };
+                        execute_bash_streaming(&arguments, &executor, on_output, cancel.clone())
+                            .await
+                            .and_then(|result| {
+                                Ok((bash_tool_result(&result)?, Some(result.structured())))
+                            })
+                    }
+                    "search" => execute_search(&arguments, &executor)
+                        .await
+                        .map(|results| (executor.bound_output(results.format()), Some(results.structured()))),
+                    agent::AGENT_TOOL => self
+                        .call_agent(session, executor, params, cancel)
+                        .await
+                        .map(|text| (text, None)),
+                    _ => execute_tool(tool_name, &arguments, &executor)
+                        .await
+                        .map(|text| (text, None)),
+                };
+
+                let result = match output {
+                    Ok((output, structured)) => {
+                        let mut result = json!({
+                            "content": [{
+                                "type": "text",
+                                "text": output
+                            }]
+                        });
+                        if let Some(structured) = structured {
+                            result["structuredContent"] = structured;
+                        }
+                        result
+                    }
                     Err(e) => json!({
                         "content": [{
                             "type": "text",
@@ -105,17 +505,135 @@ impl MCPServer {
                     }),
                 };
 
-                result
+                Ok(result)
             }
 
-            _ => {
-                json!({
-                    "error": {
-                        "code": -32601,
-                        "message": format!("Method not found: {}", method)
-                    }
-                })
+            "resources/list" => {
+                let Some(executor) = self.resource_executor(session).await else {
+                    return Ok(json!({ "resources": [] }));
+                };
+                self.resources
+                    .list(params["cursor"].as_str(), &executor)
+                    .map_err(|e| RpcError::invalid_params(e.to_string()))
+            }
+
+            "resources/read" => {
+                let uri = params["uri"]
+                    .as_str()
+                    .ok_or_else(|| RpcError::invalid_params("Missing 'uri' parameter"))?;
+                let executor = self.resource_executor(session).await.ok_or_else(|| {
+                    RpcError::resource_not_found("The client has not shared any local roots, so no resources are available")
+                })?;
+
+                self.resources
+                    .read(uri, &executor)
+                    .map_err(|e| RpcError::resource_not_found(format!("{:#}", e)))
+            }
+
+            "resources/templates/list" => Ok(self.resources.templates()),
+
+            "resources/subscribe" => {
+                let uri = params["uri"]
+                    .as_str()
+                    .ok_or_else(|| RpcError::invalid_params("Missing 'uri' parameter"))?;
+                let executor = self.resource_executor(session).await.ok_or_else(|| {
+                    RpcError::resource_not_found("The client has not shared any local roots, so no resources are available")
+                })?;
+                let path = self
+                    .resources
+                    .resolve(uri, &executor)
+                    .map_err(|e| RpcError::resource_not_found(format!("{:#}", e)))?;
+                // Normalize so watcher events match the subscribed URI
+                let uri = self
+                    .resources
+                    .uri_for(&path)
+                    .unwrap_or_else(|| uri.to_string());
+
+                self.start_watcher(session)
+                    .map_err(|e| RpcError::internal(format!("{:#}", e)))?;
+
+                session.subscriptions.lock().unwrap().insert(uri);
+                Ok(json!({}))
+            }
+
+            "resources/unsubscribe" => {
+                let uri = params["uri"]
+                    .as_str()
+                    .ok_or_else(|| RpcError::invalid_params("Missing 'uri' parameter"))?;
+                let uri = self
+                    .resources
+                    .path_for(uri)
+                    .ok()
+                    .and_then(|path| self.resources.uri_for(&path))
+                    .unwrap_or_else(|| uri.to_string());
+
+                session.subscriptions.lock().unwrap().remove(&uri);
+                Ok(json!({}))
             }
+
+            "prompts/list" => {
+                let prompts: Vec<_> = self
+                    .prompts
+                    .iter()
+                    .map(|p| {
+                        json!({
+                            "name": p.name,
+                            "description": p.description,
+                            "arguments": p.arguments
+                        })
+                    })
+                    .collect();
+
+                Ok(json!({
+                    "prompts": prompts
+                }))
+            }
+
+            "prompts/get" => {
+                let name = params["name"]
+                    .as_str()
+                    .ok_or_else(|| RpcError::invalid_params("Missing 'name' parameter"))?;
+                let prompt = self
+                    .prompts
+                    .get(name)
+                    .ok_or_else(|| RpcError::invalid_params(format!("Unknown prompt: {}", name)))?;
+
+                let args: HashMap<String, String> = params["arguments"]
+                    .as_object()
+                    .map(|a| {
+                        a.iter()
+                            .map(|(k, v)| {
+                                let value = v.as_str().map(str::to_string);
+                                (k.clone(), value.unwrap_or_else(|| v.to_string()))
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let text = prompt
+                    .render(&args)
+                    .map_err(|e| RpcError::invalid_params(e.to_string()))?;
+
+                Ok(json!({
+                    "description": prompt.description,
+                    "messages": [{
+                        "role": "user",
+                        "content": {
+                            "type": "text",
+                            "text": text
+                        }
+                    }]
+                }))
+            }
+
+            _ => Err(RpcError::method_not_found(method)),
         }
     }
 }
+
+/// Echo the client's protocol version if supported, otherwise offer our latest
+fn negotiate_version(requested: Option<&str>) -> &'static str {
+    PROTOCOL_VERSIONS
+        .iter()
+        .find(|v| Some(**v) == requested)
+        .unwrap_or(&PROTOCOL_VERSIONS[0])
+}
@@ -0,0 +1,65 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use crate::config::ServerConfig;
+use crate::shell::is_read_only_tool;
+
+/// Limits on what MCP clients may do through this server
+#[derive(Debug, Clone, Default)]
+pub struct ServerPolicy {
+    /// Tools clients may see and call; every tool when `None`
+    pub allowed_tools: Option<BTreeSet<String>>,
+    /// Hide every tool that can modify the system
+    pub read_only: bool,
+    /// Explicit roots for file tools; these take precedence over client roots
+    pub roots: Vec<PathBuf>,
+}
+
+impl ServerPolicy {
+    pub fn from_config(config: &ServerConfig) -> Result<Self> {
+        let policy = Self {
+            allowed_tools: config
+                .allowed_tools
+                .as_ref()
+                .map(|tools| tools.iter().cloned().collect()),
+            read_only: config.read_only,
+            roots: Vec::new(),
+        };
+
+        policy.with_roots(config.roots.clone())
+    }
+
+    pub fn with_roots(mut self, roots: Vec<PathBuf>) -> Result<Self> {
+        self.roots = roots
+            .iter()
+            .map(|root| {
+                root.canonicalize()
+                    .with_context(|| format!("Invalid root: {}", root.display()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(self)
+    }
+
+    /// Check whether a tool may be listed and called, explaining why not
+    pub fn check_tool(&self, tool_name: &str) -> Result<(), String> {
+        if let Some(allowed) = &self.allowed_tools {
+            if !allowed.contains(tool_name) {
+                return Err(format!(
+                    "Tool '{}' is not enabled on this server (allowed: {})",
+                    tool_name,
+                    allowed.iter().cloned().collect::<Vec<_>>().join(", ")
+                ));
+            }
+        }
+
+        if self.read_only && !is_read_only_tool(tool_name) {
+            return Err(format!(
+                "Tool '{}' is disabled because the server is in read-only mode",
+                tool_name
+            ));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::WalkBuilder;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Weak;
+use url::Url;
+
+use super::Session;
+use crate::shell::{SensitivePaths, ShellExecutor};
+
+/// Number of resources returned per `resources/list` page
+const PAGE_SIZE: usize = 100;
+
+/// Files larger than this are not served through `resources/read`
+const MAX_RESOURCE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Exposes the files under the executor's workdir as MCP resources
+pub struct ResourceProvider {
+    root: PathBuf,
+    sensitive: SensitivePaths,
+}
+
+impl ResourceProvider {
+    pub fn new(executor: &ShellExecutor) -> Result<Self> {
+        let root = executor.workdir();
+        let root = root
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve resource root: {}", root.display()))?;
+
+        Ok(Self {
+            root,
+            sensitive: executor.sensitive_paths().clone(),
+        })
+    }
+
+    /// List one page of workspace files, honoring .gitignore and leaving
+    /// out those the file tools of `executor` couldn't read
+    pub fn list(&self, cursor: Option<&str>, executor: &ShellExecutor) -> Result<Value> {
+        let start = match cursor {
+            Some(cursor) => cursor.parse::<usize>().context("Invalid cursor")?,
+            None => 0,
+        };
+
+        let mut files: Vec<PathBuf> = WalkBuilder::new(&self.root)
+            .build()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+            .map(|entry| entry.into_path())
+            .filter(|path| executor.resolve_path(&path.to_string_lossy()).is_ok())
+            .collect();
+        files.sort();
+
+        let resources: Vec<Value> = files
+            .iter()
+            .skip(start)
+            .take(PAGE_SIZE)
+            .filter_map(|path| self.describe(path))
+            .collect();
+
+        let mut result = json!({ "resources": resources });
+        if start + PAGE_SIZE < files.len() {
+            result["nextCursor"] = json!((start + PAGE_SIZE).to_string());
+        }
+
+        Ok(result)
+    }
+
+    /// Read a single resource as text, or as base64 when it is not UTF-8
+    pub fn read(&self, uri: &str, executor: &ShellExecutor) -> Result<Value> {
+        let path = self.resolve(uri, executor)?;
+
+        let metadata = std::fs::metadata(&path)
+            .with_context(|| format!("Resource not found: {}", uri))?;
+        if !metadata.is_file() {
+            anyhow::bail!("Resource is not a file: {}", uri);
+        }
+        if metadata.len() > MAX_RESOURCE_SIZE {
+            anyhow::bail!(
+                "Resource too large: {} ({} bytes, limit {})",
+                uri,
+                metadata.len(),
+                MAX_RESOURCE_SIZE
+            );
+        }
+
+        let bytes = std::fs::read(&path)
+            .with_context(|| format!("Failed to read resource: {}", uri))?;
+        let mime_type = mime_type(&path);
+
+        let content = match String::from_utf8(bytes) {
+            Ok(text) => json!({
+                "uri": uri,
+                "mimeType": mime_type.unwrap_or_else(|| "text/plain".to_string()),
+                "text": text
+            }),
+            Err(e) => json!({
+                "uri": uri,
+                "mimeType": mime_type.unwrap_or_else(|| "application/octet-stream".to_string()),
+                "blob": base64::engine::general_purpose::STANDARD.encode(e.into_bytes())
+            }),
+        };
+
+        Ok(json!({ "contents": [content] }))
+    }
+
+    pub fn templates(&self) -> Value {
+        let base = Url::from_directory_path(&self.root)
+            .map(|url| url.to_string())
+            .unwrap_or_else(|_| format!("file://{}/", self.root.display()));
+
+        json!({
+            "resourceTemplates": [{
+                "uriTemplate": format!("{}{{path}}", base),
+                "name": "Workspace file",
+                "description": "Any file in the aishell workspace, by path relative to the workspace root"
+            }]
+        })
+    }
+
+    pub fn uri_for(&self, path: &Path) -> Option<String> {
+        Url::from_file_path(path).ok().map(|url| url.to_string())
+    }
+
+    /// Resolve a `file://` URI to a path inside the workspace root
+    pub fn path_for(&self, uri: &str) -> Result<PathBuf> {
+        let url = Url::parse(uri).with_context(|| format!("Invalid resource URI: {}", uri))?;
+        if url.scheme() != "file" {
+            anyhow::bail!("Unsupported resource URI scheme: {}", url.scheme());
+        }
+
+        let path = url
+            .to_file_path()
+            .map_err(|_| anyhow::anyhow!("Invalid file URI: {}", uri))?;
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Resource not found: {}", uri))?;
+
+        if !path.starts_with(&self.root) {
+            anyhow::bail!("Resource is outside the workspace: {}", uri);
+        }
+
+        Ok(path)
+    }
+
+    /// Resolve a `file://` URI to a workspace path that the file tools of
+    /// `executor` may use, so roots and sensitive paths apply to resources
+    pub fn resolve(&self, uri: &str, executor: &ShellExecutor) -> Result<PathBuf> {
+        let path = self.path_for(uri)?;
+        executor.resolve_path(&path.to_string_lossy())
+    }
+
+    /// Start watching the workspace, forwarding changes to the session.
+    /// Files `list` leaves out, like hidden, ignored or sensitive ones,
+    /// don't count as changes to the list.
+    pub fn watch(&self, session: Weak<Session>) -> Result<RecommendedWatcher> {
+        let root = self.root.clone();
+        let ignored = ignore_rules(&self.root);
+        let sensitive = self.sensitive.clone();
+
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    tracing::warn!("Resource watcher error: {}", e);
+                    return;
+                }
+            };
+            let Some(session) = session.upgrade() else {
+                return;
+            };
+
+            if (matches!(event.kind, EventKind::Create(_) | EventKind::Remove(_))
+                || matches!(event.kind, EventKind::Modify(notify::event::ModifyKind::Name(_))))
+                && event.paths.iter().any(|path| {
+                    is_listed(&root, &ignored, path) && sensitive.matching(path).is_none()
+                })
+            {
+                session.peer.notify("notifications/resources/list_changed", json!({}));
+            }
+
+            for path in &event.paths {
+                if !path.starts_with(&root) || sensitive.matching(path).is_some() {
+                    continue;
+                }
+                let Ok(url) = Url::from_file_path(path) else {
+                    continue;
+                };
+                let uri = url.to_string();
+                if session.is_subscribed(&uri) {
+                    session
+                        .peer
+                        .notify("notifications/resources/updated", json!({ "uri": uri }));
+                }
+            }
+        })
+        .context("Failed to create resource watcher")?;
+
+        watcher
+            .watch(&self.root, RecursiveMode::Recursive)
+            .context("Failed to watch workspace")?;
+
+        Ok(watcher)
+    }
+
+    fn describe(&self, path: &Path) -> Option<Value> {
+        let uri = self.uri_for(path)?;
+        let name = path.strip_prefix(&self.root).ok()?.to_string_lossy().to_string();
+        let size = std::fs::metadata(path).map(|m| m.len()).ok();
+
+        let mut resource = json!({
+            "uri": uri,
+            "name": name,
+            "size": size
+        });
+        if let Some(mime_type) = mime_type(path) {
+            resource["mimeType"] = json!(mime_type);
+        }
+
+        Some(resource)
+    }
+}
+
+fn mime_type(path: &Path) -> Option<String> {
+    mime_guess::from_path(path)
+        .first()
+        .map(|mime| mime.essence_str().to_string())
+}
+
+/// The workspace's top-level ignore files, as `list` applies them
+fn ignore_rules(root: &Path) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(root);
+    for file in [".gitignore", ".ignore", ".git/info/exclude"] {
+        let path = root.join(file);
+        if path.is_file() {
+            if let Some(e) = builder.add(&path) {
+                tracing::warn!("Failed to read {}: {}", path.display(), e);
+            }
+        }
+    }
+    builder.build().unwrap_or_else(|_| Gitignore::empty())
+}
+
+/// Whether `path` is a file `list` would include: inside the root, not
+/// hidden and not ignored
+fn is_listed(root: &Path, ignored: &Gitignore, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return false;
+    };
+    let hidden = relative
+        .components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+
+    !hidden && !ignored.matched_path_or_any_parents(path, path.is_dir()).is_ignore()
+}
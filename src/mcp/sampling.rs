@@ -0,0 +1,165 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use crate::llm::provider::FunctionCall;
+use crate::llm::{ChatResponse, LLMProvider, Message, Role, ToolCall, ToolDefinition};
+
+use super::Peer;
+
+/// Upper bound on tokens requested per sampling call
+const MAX_TOKENS: u64 = 4096;
+
+/// An LLM provider that borrows the MCP client's model through
+/// `sampling/createMessage`, so the server needs no API key of its own
+pub struct SamplingProvider {
+    peer: Peer,
+    /// Whether the client accepts `tools` in sampling requests
+    supports_tools: bool,
+    model_hint: Option<String>,
+}
+
+impl SamplingProvider {
+    pub fn new(peer: Peer, supports_tools: bool, model_hint: Option<String>) -> Self {
+        Self {
+            peer,
+            supports_tools,
+            model_hint,
+        }
+    }
+
+    fn convert_message(&self, message: &Message) -> Value {
+        match message.role {
+            Role::Tool if self.supports_tools => json!({
+                "role": "user",
+                "content": [{
+                    "type": "tool_result",
+                    "toolUseId": message.tool_call_id,
+                    "content": [{ "type": "text", "text": message.content }]
+                }]
+            }),
+            // Without tool support, results are replayed as plain text
+            Role::Tool => text_message("user", &format!("Tool result:\n{}", message.content)),
+            Role::Assistant => {
+                let Some(tool_calls) = &message.tool_calls else {
+                    return text_message("assistant", &message.content);
+                };
+
+                if !self.supports_tools {
+                    let calls: Vec<String> = tool_calls
+                        .iter()
+                        .map(|c| format!("[called {} with {}]", c.function.name, c.function.arguments))
+                        .collect();
+                    let text = format!("{}\n{}", message.content, calls.join("\n"));
+                    return text_message("assistant", text.trim());
+                }
+
+                let mut content = Vec::new();
+                if !message.content.is_empty() {
+                    content.push(json!({ "type": "text", "text": message.content }));
+                }
+                for call in tool_calls {
+                    let input: Value =
+                        serde_json::from_str(&call.function.arguments).unwrap_or_else(|_| json!({}));
+                    content.push(json!({
+                        "type": "tool_use",
+                        "id": call.id,
+                        "name": call.function.name,
+                        "input": input
+                    }));
+                }
+                json!({ "role": "assistant", "content": content })
+            }
+            _ => text_message("user", &message.content),
+        }
+    }
+}
+
+fn text_message(role: &str, text: &str) -> Value {
+    json!({
+        "role": role,
+        "content": { "type": "text", "text": text }
+    })
+}
+
+#[async_trait]
+impl LLMProvider for SamplingProvider {
+    async fn chat(
+        &self,
+        messages: Vec<Message>,
+        tools: Option<Vec<ToolDefinition>>,
+    ) -> Result<ChatResponse> {
+        let system_prompt: Vec<&str> = messages
+            .iter()
+            .filter(|m| matches!(m.role, Role::System))
+            .map(|m| m.content.as_str())
+            .collect();
+        let sampling_messages: Vec<Value> = messages
+            .iter()
+            .filter(|m| !matches!(m.role, Role::System))
+            .map(|m| self.convert_message(m))
+            .collect();
+
+        let mut params = json!({
+            "messages": sampling_messages,
+            "systemPrompt": system_prompt.join("\n\n"),
+            "maxTokens": MAX_TOKENS
+        });
+        if let Some(hint) = &self.model_hint {
+            params["modelPreferences"] = json!({ "hints": [{ "name": hint }] });
+        }
+        if let Some(tools) = tools.filter(|_| self.supports_tools) {
+            let tools: Vec<Value> = tools
+                .iter()
+                .map(|t| {
+                    json!({
+                        "name": t.function.name,
+                        "description": t.function.description,
+                        "inputSchema": t.function.parameters
+                    })
+                })
+                .collect();
+            params["tools"] = json!(tools);
+            params["toolChoice"] = json!({ "mode": "auto" });
+        }
+
+        let result = self
+            .peer
+            .request("sampling/createMessage", params)
+            .await
+            .context("Sampling request failed")?;
+
+        // Content is a single block, or an array when tools are in play
+        let blocks = match &result["content"] {
+            Value::Array(blocks) => blocks.clone(),
+            block => vec![block.clone()],
+        };
+
+        let mut content = Vec::new();
+        let mut tool_calls = Vec::new();
+        for block in blocks {
+            match block["type"].as_str() {
+                Some("text") => content.push(block["text"].as_str().unwrap_or("").to_string()),
+                Some("tool_use") => tool_calls.push(ToolCall {
+                    id: block["id"].as_str().unwrap_or("").to_string(),
+                    call_type: "function".to_string(),
+                    function: FunctionCall {
+                        name: block["name"].as_str().unwrap_or("").to_string(),
+                        arguments: block["input"].to_string(),
+                    },
+                }),
+                other => tracing::debug!("Ignoring sampling content of type {:?}", other),
+            }
+        }
+
+        Ok(ChatResponse {
+            content: content.join("\n"),
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            finish_reason: result["stopReason"].as_str().unwrap_or("endTurn").to_string(),
+        })
+    }
+
+    fn model_name(&self) -> &str {
+        "mcp-sampling"
+    }
+}
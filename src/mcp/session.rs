@@ -0,0 +1,274 @@
+use anyhow::Result;
+use notify::RecommendedWatcher;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use super::{LogLevel, RpcError};
+use crate::permissions::{Approval, ApprovalRequest, Permissions};
+use crate::shell::PersistentShell;
+
+/// How long to wait for the client to answer a server-initiated request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+type PendingRequests = Mutex<HashMap<u64, oneshot::Sender<Result<Value, RpcError>>>>;
+
+/// Where a peer's messages go: straight to a writer, or into a buffer the
+/// client may not be draining
+#[derive(Clone)]
+enum Outgoing {
+    Unbounded(mpsc::UnboundedSender<Value>),
+    Bounded(mpsc::Sender<Value>),
+}
+
+/// Handle for sending messages to the connected client
+#[derive(Clone)]
+pub struct Peer {
+    tx: Outgoing,
+    next_id: Arc<AtomicU64>,
+    pending: Arc<PendingRequests>,
+    /// Whether messages are being dropped because the buffer is full
+    dropping: Arc<AtomicBool>,
+}
+
+impl Peer {
+    pub fn new(tx: mpsc::UnboundedSender<Value>) -> Self {
+        Self::with_outgoing(Outgoing::Unbounded(tx))
+    }
+
+    /// A peer whose messages are dropped while `tx` is full, for clients
+    /// that only read them when they have a stream open
+    pub fn bounded(tx: mpsc::Sender<Value>) -> Self {
+        Self::with_outgoing(Outgoing::Bounded(tx))
+    }
+
+    fn with_outgoing(tx: Outgoing) -> Self {
+        Self {
+            tx,
+            next_id: Arc::new(AtomicU64::new(1)),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            dropping: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Send a JSON-RPC notification to the client
+    pub fn notify(&self, method: &str, params: Value) {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params
+        }));
+    }
+
+    /// Send a request to the client and wait for its response
+    pub async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params
+        }));
+
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, rx).await;
+        self.pending.lock().unwrap().remove(&id);
+
+        match response {
+            Ok(Ok(Ok(result))) => Ok(result),
+            Ok(Ok(Err(e))) => anyhow::bail!("Client returned error for {}: {}", method, e.message),
+            Ok(Err(_)) => anyhow::bail!("Connection closed before {} completed", method),
+            Err(_) => anyhow::bail!("Timed out waiting for client to answer {}", method),
+        }
+    }
+
+    /// Deliver a response from the client to the request waiting on it
+    pub(super) fn resolve(&self, message: &Value) {
+        let Some(id) = message["id"].as_u64() else {
+            tracing::warn!("Ignoring response with unexpected id: {}", message["id"]);
+            return;
+        };
+        let Some(tx) = self.pending.lock().unwrap().remove(&id) else {
+            tracing::warn!("Ignoring response to unknown request {}", id);
+            return;
+        };
+
+        let result = match message.get("error") {
+            Some(error) => Err(RpcError {
+                code: error["code"].as_i64().unwrap_or(-32603),
+                message: error["message"].as_str().unwrap_or("").to_string(),
+            }),
+            None => Ok(message["result"].clone()),
+        };
+        let _ = tx.send(result);
+    }
+
+    pub(super) fn send(&self, message: Value) {
+        // The receiver only goes away when the connection is closing
+        match &self.tx {
+            Outgoing::Unbounded(tx) => {
+                let _ = tx.send(message);
+            }
+            Outgoing::Bounded(tx) => match tx.try_send(message) {
+                Ok(()) => self.dropping.store(false, Ordering::Relaxed),
+                Err(TrySendError::Full(_)) => {
+                    // Warn once per overflow; the warning is itself forwarded
+                    if !self.dropping.swap(true, Ordering::Relaxed) {
+                        tracing::warn!("Client is not reading its message stream; dropping messages until it does");
+                    }
+                }
+                Err(TrySendError::Closed(_)) => {}
+            },
+        }
+    }
+}
+
+/// Source of `Session::id`
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// State kept for one connected client
+pub struct Session {
+    /// Tags the session's log records
+    pub(super) id: u64,
+    pub(super) peer: Peer,
+    pub(super) subscriptions: Mutex<HashSet<String>>,
+    pub(super) watcher: Mutex<Option<RecommendedWatcher>>,
+    /// Requests still being handled, keyed by their serialized JSON-RPC id
+    pub(super) in_flight: Mutex<HashMap<String, CancellationToken>>,
+    /// Capabilities the client declared in `initialize`
+    pub(super) client_capabilities: Mutex<Value>,
+    /// Directories the client exposed through `roots/list`, if it has any
+    pub(super) roots: Mutex<Option<Vec<PathBuf>>>,
+    /// Number of `roots/list` requests awaiting an answer
+    pub(super) roots_pending: watch::Sender<usize>,
+    /// Minimum severity forwarded as `notifications/message`
+    pub(super) log_level: Mutex<LogLevel>,
+    /// Whether log records are already being forwarded
+    pub(super) log_forwarding: AtomicBool,
+    /// The session's own shell, used when persistent shells are enabled
+    pub(super) shell: PersistentShell,
+    /// Rules plus the calls this client's user approved for the session
+    pub(super) permissions: Mutex<Option<Permissions>>,
+}
+
+impl Session {
+    pub fn new(peer: Peer) -> Self {
+        Self {
+            id: NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
+            peer,
+            subscriptions: Mutex::new(HashSet::new()),
+            watcher: Mutex::new(None),
+            in_flight: Mutex::new(HashMap::new()),
+            client_capabilities: Mutex::new(Value::Null),
+            roots: Mutex::new(None),
+            roots_pending: watch::Sender::new(0),
+            log_level: Mutex::new(LogLevel::Warning),
+            log_forwarding: AtomicBool::new(false),
+            shell: PersistentShell::new(),
+            permissions: Mutex::new(None),
+        }
+    }
+
+    pub fn is_subscribed(&self, uri: &str) -> bool {
+        self.subscriptions.lock().unwrap().contains(uri)
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        let capabilities = self.client_capabilities.lock().unwrap();
+        capabilities.get(capability).is_some_and(|c| !c.is_null())
+    }
+
+    /// Ask the client's user to approve a tool call through elicitation
+    pub async fn ask_approval(&self, request: &ApprovalRequest) -> Approval {
+        let mut message = match request.subject.as_str() {
+            "" => format!("aishell wants to use the {} tool", request.tool),
+            subject => format!("aishell wants to use the {} tool on: {}", request.tool, subject),
+        };
+        if let Some(reason) = &request.reason {
+            message.push_str(&format!("\nWarning: {}", reason));
+        }
+        let params = json!({
+            "message": message,
+            "requestedSchema": {
+                "type": "object",
+                "properties": {
+                    "decision": {
+                        "type": "string",
+                        "title": "Decision",
+                        "enum": ["once", "session", "deny"],
+                        "enumNames": ["Allow once", "Allow for this session", "Deny"]
+                    }
+                },
+                "required": ["decision"]
+            }
+        });
+
+        match self.peer.request("elicitation/create", params).await {
+            Ok(result) if result["action"] == "accept" => {
+                match result["content"]["decision"].as_str() {
+                    Some("once") => Approval::Once,
+                    Some("session") => Approval::Session,
+                    _ => Approval::Deny,
+                }
+            }
+            Ok(_) => Approval::Deny,
+            Err(e) => {
+                tracing::warn!("Failed to ask for approval: {:#}", e);
+                Approval::Deny
+            }
+        }
+    }
+
+    /// The client's roots once any request for them has been answered.
+    /// Empty if it supports roots but they never arrived, so nothing runs
+    /// unconfined in the meantime.
+    pub async fn client_roots(&self) -> Option<Vec<PathBuf>> {
+        let mut pending = self.roots_pending.subscribe();
+        let _ = pending.wait_for(|pending| *pending == 0).await;
+
+        let roots = self.roots.lock().unwrap().clone();
+        match roots {
+            None if self.supports("roots") => Some(Vec::new()),
+            roots => roots,
+        }
+    }
+
+    /// Ask the client for its roots and remember the local ones
+    pub async fn refresh_roots(&self) -> Result<()> {
+        let result = self.peer.request("roots/list", json!({})).await?;
+
+        let roots: Vec<PathBuf> = result["roots"]
+            .as_array()
+            .map(|roots| {
+                roots
+                    .iter()
+                    .filter_map(|root| root["uri"].as_str())
+                    .filter_map(|uri| Url::parse(uri).ok())
+                    .filter_map(|url| url.to_file_path().ok())
+                    .filter_map(|path| path.canonicalize().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        tracing::info!(
+            "Client roots: {}",
+            roots
+                .iter()
+                .map(|r| r.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        *self.roots.lock().unwrap() = Some(roots);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,822 @@
+mod danger;
+
+use anyhow::{Context, Result};
+use globset::{Glob, GlobBuilder, GlobMatcher};
+use serde_json::Value;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::config::{PermissionMode, PermissionsConfig};
+use crate::shell::parser::SimpleCommand;
+use crate::shell::paths::canonicalize_lenient;
+use crate::shell::{parser, patch};
+
+pub use danger::Danger;
+
+/// Project file that rules chosen with "always (save)" are written to
+const PROJECT_CONFIG: &str = ".aishell/config.toml";
+
+/// File in the user's config directory recording, per project, the allow
+/// rules from its config the user has saved or trusted. A checked-out
+/// project can't grant itself permissions by shipping its own rules.
+const TRUST_FILE: &str = "trusted_projects.toml";
+
+/// What to do with a tool call before running it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// Run only if every request is approved
+    Ask(Vec<ApprovalRequest>),
+    Deny(String),
+}
+
+/// The user's answer to an approval prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Approval {
+    /// Run this call only
+    Once,
+    /// Allow calls matching the suggested rule for the rest of the session
+    Session,
+    /// Allow matching calls from now on, saving the rule to the project config
+    Persist,
+    Deny,
+}
+
+/// A tool call waiting for approval
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApprovalRequest {
+    pub tool: String,
+    /// The command or path the call acts on
+    pub subject: String,
+    /// Why this needs a closer look, e.g. "recursive delete of /"
+    pub reason: Option<String>,
+    /// Rules that "always" would add, e.g. `bash(git status)`
+    pub rules: Vec<String>,
+}
+
+impl ApprovalRequest {
+    fn new(tool: &str, subject: &str) -> Self {
+        let rule = match subject {
+            "" => tool.to_string(),
+            subject => format!("{}({})", tool, escape_glob(subject)),
+        };
+        Self {
+            tool: tool.to_string(),
+            subject: subject.to_string(),
+            reason: None,
+            rules: vec![rule],
+        }
+    }
+}
+
+/// A rule such as `bash`, `bash(git log:*)`, `bash(npm run *)` or `write(src/**)`
+#[derive(Debug, Clone)]
+struct Rule {
+    source: String,
+    tool: String,
+    pattern: Option<Pattern>,
+}
+
+#[derive(Debug, Clone)]
+enum Pattern {
+    /// `prefix:*` matches the prefix alone or followed by a space
+    Prefix(String),
+    Glob(GlobMatcher),
+    Exact(String),
+}
+
+impl Rule {
+    fn parse(source: &str) -> Result<Self> {
+        let source = source.trim();
+        let (tool, pattern) = match source.split_once('(') {
+            Some((tool, rest)) => {
+                let pattern = rest
+                    .strip_suffix(')')
+                    .with_context(|| format!("Unclosed '(' in permission rule: {}", source))?;
+                (tool.trim(), Some(pattern))
+            }
+            None => (source, None),
+        };
+        if tool.is_empty() {
+            anyhow::bail!("Missing tool name in permission rule: {}", source);
+        }
+
+        let pattern = match pattern {
+            None | Some("*") | Some("") => None,
+            Some(pattern) => Some(if let Some(prefix) = pattern.strip_suffix(":*") {
+                Pattern::Prefix(prefix.to_string())
+            } else if let Some(literal) = unescape_glob(pattern) {
+                // Approved calls are saved escaped and match only themselves
+                Pattern::Exact(literal)
+            } else {
+                // Paths use `**` to cross directories; commands match freely
+                let glob = match tool {
+                    "bash" => Glob::new(pattern),
+                    _ => GlobBuilder::new(pattern).literal_separator(true).build(),
+                }
+                .with_context(|| format!("Invalid pattern in permission rule: {}", source))?;
+                Pattern::Glob(glob.compile_matcher())
+            }),
+        };
+
+        Ok(Self {
+            source: source.to_string(),
+            tool: tool.to_string(),
+            pattern,
+        })
+    }
+
+    /// Whether the rule names one exact subject (or the whole tool)
+    fn is_literal(&self) -> bool {
+        matches!(self.pattern, None | Some(Pattern::Exact(_)))
+    }
+
+    fn matches(&self, tool: &str, subject: &str) -> bool {
+        if self.tool != "*" && self.tool != tool {
+            return false;
+        }
+        match &self.pattern {
+            None => true,
+            Some(Pattern::Prefix(prefix)) => {
+                subject == prefix || subject.starts_with(&format!("{} ", prefix))
+            }
+            Some(Pattern::Glob(glob)) => glob.is_match(subject),
+            Some(Pattern::Exact(exact)) => subject == exact,
+        }
+    }
+}
+
+/// Allow, ask and deny rules consulted before every tool call. Clones share
+/// the rules approved during the session; `fork` starts a fresh session.
+#[derive(Debug, Clone)]
+pub struct Permissions {
+    mode: PermissionMode,
+    allow: Vec<Rule>,
+    ask: Vec<Rule>,
+    deny: Vec<Rule>,
+    approved: Arc<Mutex<Vec<Rule>>>,
+    project_dir: Option<PathBuf>,
+    /// Allow rules in the project config that are ignored until trusted
+    untrusted: Vec<String>,
+}
+
+impl Default for Permissions {
+    /// Allow everything, as when no permissions are configured
+    fn default() -> Self {
+        Self {
+            mode: PermissionMode::Auto,
+            allow: Vec::new(),
+            ask: Vec::new(),
+            deny: Vec::new(),
+            approved: Arc::new(Mutex::new(Vec::new())),
+            project_dir: None,
+            untrusted: Vec::new(),
+        }
+    }
+}
+
+impl Permissions {
+    /// Build from the user config plus rules saved in the project's
+    /// `.aishell/config.toml`. The project file can add rules but can't
+    /// change the mode, and its allow rules only count once the user has
+    /// saved or trusted them.
+    pub fn load(config: &PermissionsConfig, project_dir: &Path) -> Result<Self> {
+        let mut permissions = Self::from_config(config)?;
+        permissions.project_dir = Some(project_dir.to_path_buf());
+
+        let path = project_dir.join(PROJECT_CONFIG);
+        if path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let project: ProjectConfig = toml::from_str(&content)
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+            let trusted = trusted_rules(project_dir)?;
+            let (allow, untrusted): (Vec<String>, Vec<String>) = project
+                .permissions
+                .allow
+                .iter()
+                .cloned()
+                .partition(|rule| trusted.contains(rule) || config.allow.contains(rule));
+            if !untrusted.is_empty() {
+                tracing::warn!(
+                    "Ignoring allow rules in {} that haven't been trusted: {}",
+                    path.display(),
+                    untrusted.join(", ")
+                );
+            }
+            permissions.add_rules(&PermissionsConfig {
+                allow,
+                ..project.permissions
+            })?;
+            permissions.untrusted = untrusted;
+        }
+
+        Ok(permissions)
+    }
+
+    /// Allow rules in the project config that are ignored until trusted
+    pub fn untrusted_rules(&self) -> &[String] {
+        &self.untrusted
+    }
+
+    /// Trust the project config's untrusted allow rules, for this session
+    /// and from now on
+    pub fn trust_project_rules(&self) -> Result<()> {
+        let dir = self
+            .project_dir
+            .as_ref()
+            .context("No project directory to trust")?;
+        record_trust(dir, &self.untrusted)?;
+        for rule in &self.untrusted {
+            self.approve(rule)?;
+        }
+        Ok(())
+    }
+
+    pub fn from_config(config: &PermissionsConfig) -> Result<Self> {
+        let mut permissions = Self {
+            mode: config.mode,
+            ..Self::default()
+        };
+        permissions.add_rules(config)?;
+        Ok(permissions)
+    }
+
+    fn add_rules(&mut self, config: &PermissionsConfig) -> Result<()> {
+        for (rules, sources) in [
+            (&mut self.allow, &config.allow),
+            (&mut self.ask, &config.ask),
+            (&mut self.deny, &config.deny),
+        ] {
+            for source in sources {
+                rules.push(Rule::parse(source)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// The same rules with no session approvals
+    pub fn fork(&self) -> Self {
+        Self {
+            approved: Arc::new(Mutex::new(Vec::new())),
+            ..self.clone()
+        }
+    }
+
+    /// Decide whether a call may run; relative paths resolve against `base`.
+    /// Deny rules win over ask rules, which win over allow rules.
+    pub fn check(&self, tool: &str, arguments: &Value, base: &Path) -> Decision {
+        if tool == "bash" {
+            if let Some(command) = arguments["command"].as_str() {
+                return self.check_command(command.trim(), base);
+            }
+        }
+        if tool == "apply_patch" {
+            if let Some(decision) = arguments["patch"].as_str().and_then(|p| self.check_patch(p, base)) {
+                return decision;
+            }
+        }
+
+        let subject = subject(tool, arguments, base);
+        match self.lookup(tool, &subject) {
+            Some(Lookup::Deny(rule)) => Decision::Deny(format!("denied by rule `{}`", rule)),
+            Some(Lookup::Ask) => Decision::Ask(vec![ApprovalRequest::new(tool, &subject)]),
+            Some(Lookup::Allow(_)) => Decision::Allow,
+            None => self.by_mode(tool, vec![ApprovalRequest::new(tool, &subject)]),
+        }
+    }
+
+    /// Check each file a patch touches on its own, so that
+    /// `apply_patch(src/**)` only covers patches that stay within `src`.
+    /// `None` if the patch can't be parsed, leaving the tool to report it.
+    fn check_patch(&self, patch: &str, base: &Path) -> Option<Decision> {
+        let mut paths: Vec<String> = Vec::new();
+        for file in patch::parse_patch(patch).ok()? {
+            for path in [file.old_path, file.new_path].into_iter().flatten() {
+                let path = relative_path(&path, base);
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+
+        let mut unmatched = Vec::new();
+        let mut ask = false;
+        for path in paths {
+            match self.lookup("apply_patch", &path) {
+                Some(Lookup::Deny(rule)) => {
+                    return Some(Decision::Deny(format!("denied by rule `{}` for {}", rule, path)))
+                }
+                Some(Lookup::Ask) => {
+                    ask = true;
+                    unmatched.push(path);
+                }
+                Some(Lookup::Allow(_)) => {}
+                None => unmatched.push(path),
+            }
+        }
+        if unmatched.is_empty() {
+            return Some(Decision::Allow);
+        }
+
+        let mut request = ApprovalRequest::new("apply_patch", &unmatched.join(", "));
+        request.rules = unmatched
+            .iter()
+            .map(|path| format!("apply_patch({})", escape_glob(path)))
+            .collect();
+        Some(match ask {
+            true => Decision::Ask(vec![request]),
+            false => self.by_mode("apply_patch", vec![request]),
+        })
+    }
+
+    /// Check each simple command in a bash command line on its own, so that
+    /// `git status:*` doesn't also allow `git status && rm -rf ~`. Destructive
+    /// sub-commands, and those redirecting output to files outside `base`
+    /// and the temp directories, are always asked about separately unless a
+    /// rule allows that exact sub-command.
+    fn check_command(&self, command: &str, base: &Path) -> Decision {
+        let whole = self.lookup("bash", command);
+        if let Some(Lookup::Deny(rule)) = &whole {
+            return Decision::Deny(format!("denied by rule `{}`", rule));
+        }
+        // A rule naming this whole command line covers all its parts, but a
+        // prefix or glob could match more than was intended
+        let whole_allowed = matches!(&whole, Some(Lookup::Allow(rule)) if rule.is_literal());
+
+        let pipelines = match parser::parse(command) {
+            Ok(pipelines) => pipelines,
+            Err(_) if whole_allowed => return Decision::Allow,
+            Err(e) => {
+                let mut request = ApprovalRequest::new("bash", command);
+                request.reason = Some(format!("could not be parsed: {}", e));
+                return match self.mode {
+                    PermissionMode::Deny => Decision::Deny(format!(
+                        "the command could not be parsed ({}) and permissions.mode is \"deny\"",
+                        e
+                    )),
+                    _ => Decision::Ask(vec![request]),
+                };
+            }
+        };
+
+        let mut requests = Vec::new();
+        let mut unmatched = Vec::new();
+
+        let dangers = danger::scan(command, &pipelines);
+        for danger in &dangers {
+            match self.lookup("bash", &danger.command) {
+                Some(Lookup::Deny(rule)) => {
+                    return Decision::Deny(format!("`{}` is denied by rule `{}`", danger.command, rule))
+                }
+                Some(Lookup::Allow(_)) => {}
+                _ if self.mode == PermissionMode::Deny => {
+                    return Decision::Deny(format!(
+                        "`{}` looks destructive ({}) and permissions.mode is \"deny\"",
+                        danger.command, danger.reason
+                    ))
+                }
+                _ => {
+                    let mut request = ApprovalRequest::new("bash", &danger.command);
+                    request.reason = Some(danger.reason.clone());
+                    requests.push(request);
+                }
+            }
+        }
+
+        let simple_commands = parser::simple_commands(&pipelines);
+        // After a `cd`, relative redirections can't be placed
+        let moves = simple_commands
+            .iter()
+            .any(|c| matches!(c.name(), Some("cd" | "pushd" | "popd")));
+
+        for simple in simple_commands {
+            let text = simple.text();
+            if dangers.iter().any(|d| d.parts.contains(&text)) {
+                continue;
+            }
+            let lookup = self.lookup("bash", &text);
+            if let Some(target) = outside_write(simple, base, moves) {
+                match lookup {
+                    Some(Lookup::Deny(rule)) => {
+                        return Decision::Deny(format!("`{}` is denied by rule `{}`", text, rule))
+                    }
+                    // A prefix rule says nothing about where output may go
+                    Some(Lookup::Allow(rule)) if rule.is_literal() => {}
+                    _ if whole_allowed => {}
+                    _ if self.mode == PermissionMode::Deny => {
+                        return Decision::Deny(format!(
+                            "`{}` writes to {} outside the working directory and permissions.mode is \"deny\"",
+                            text, target
+                        ))
+                    }
+                    _ => {
+                        let mut request = ApprovalRequest::new("bash", &text);
+                        request.reason = Some(format!("writes to {} outside the working directory", target));
+                        requests.push(request);
+                    }
+                }
+                continue;
+            }
+            match lookup {
+                Some(Lookup::Deny(rule)) => {
+                    return Decision::Deny(format!("`{}` is denied by rule `{}`", text, rule))
+                }
+                Some(Lookup::Ask) => requests.push(ApprovalRequest::new("bash", &text)),
+                Some(Lookup::Allow(_)) => {}
+                None if whole_allowed => {}
+                None => unmatched.push(text),
+            }
+        }
+
+        if matches!(whole, Some(Lookup::Ask)) {
+            requests.push(ApprovalRequest::new("bash", command));
+        } else if !unmatched.is_empty() {
+            // One prompt for the ordinary parts that remembers each of them
+            unmatched.dedup();
+            let mut request = ApprovalRequest::new("bash", command);
+            request.rules = unmatched
+                .iter()
+                .map(|text| format!("bash({})", escape_glob(text)))
+                .collect();
+            match self.by_mode("bash", vec![request]) {
+                Decision::Ask(mut ordinary) => requests.append(&mut ordinary),
+                Decision::Allow => {}
+                deny => return deny,
+            }
+        }
+
+        requests.dedup();
+        match requests.is_empty() {
+            true => Decision::Allow,
+            false => Decision::Ask(requests),
+        }
+    }
+
+    /// The first rule matching a call, by precedence
+    fn lookup(&self, tool: &str, subject: &str) -> Option<Lookup> {
+        if let Some(rule) = self.deny.iter().find(|r| r.matches(tool, subject)) {
+            return Some(Lookup::Deny(rule.source.clone()));
+        }
+        if self.ask.iter().any(|r| r.matches(tool, subject)) {
+            return Some(Lookup::Ask);
+        }
+        let approved = self.approved.lock().unwrap();
+        self.allow
+            .iter()
+            .chain(approved.iter())
+            .find(|r| r.matches(tool, subject))
+            .map(|rule| Lookup::Allow(rule.clone()))
+    }
+
+    /// What to do when no rule matches
+    fn by_mode(&self, tool: &str, requests: Vec<ApprovalRequest>) -> Decision {
+        match self.mode {
+            PermissionMode::Auto => Decision::Allow,
+            PermissionMode::Ask => Decision::Ask(requests),
+            PermissionMode::Deny => Decision::Deny(format!(
+                "`{}` is not allowed by any rule and permissions.mode is \"deny\"",
+                tool
+            )),
+        }
+    }
+
+    /// Apply the user's answer, remembering or saving the rule it implies.
+    /// Returns whether the call may run.
+    pub fn resolve(&self, request: &ApprovalRequest, approval: Approval) -> Result<bool> {
+        match approval {
+            Approval::Once => Ok(true),
+            Approval::Deny => Ok(false),
+            Approval::Session => {
+                for rule in &request.rules {
+                    self.approve(rule)?;
+                }
+                Ok(true)
+            }
+            Approval::Persist => {
+                for rule in &request.rules {
+                    self.approve(rule)?;
+                    self.save(rule)?;
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    fn approve(&self, rule: &str) -> Result<()> {
+        let rule = Rule::parse(rule)?;
+        self.approved.lock().unwrap().push(rule);
+        Ok(())
+    }
+
+    /// Append an allow rule to the project's `.aishell/config.toml`
+    fn save(&self, rule: &str) -> Result<()> {
+        let dir = self
+            .project_dir
+            .as_ref()
+            .context("No project directory to save the rule to")?;
+        let path = dir.join(PROJECT_CONFIG);
+
+        let mut document: toml::Table = match path.exists() {
+            true => std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?
+                .parse()
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            false => toml::Table::new(),
+        };
+
+        let section = document
+            .entry("permissions")
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .context("[permissions] in the project config is not a table")?;
+        let allow = section
+            .entry("allow")
+            .or_insert_with(|| toml::Value::Array(Vec::new()))
+            .as_array_mut()
+            .context("permissions.allow in the project config is not an array")?;
+        if !allow.iter().any(|r| r.as_str() == Some(rule)) {
+            allow.push(toml::Value::String(rule.to_string()));
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, toml::to_string_pretty(&document)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        record_trust(dir, &[rule.to_string()])?;
+        tracing::info!("Saved permission rule {} to {}", rule, path.display());
+
+        Ok(())
+    }
+}
+
+fn trust_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("aishell").join(TRUST_FILE))
+}
+
+/// The trust file's table, keyed by canonical project directory
+fn read_trust() -> Result<toml::Table> {
+    let Some(path) = trust_path().filter(|path| path.exists()) else {
+        return Ok(toml::Table::new());
+    };
+    std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .parse()
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn project_key(project_dir: &Path) -> String {
+    let dir = project_dir.canonicalize().unwrap_or_else(|_| project_dir.to_path_buf());
+    dir.to_string_lossy().to_string()
+}
+
+/// Allow rules from the project's config the user has trusted
+fn trusted_rules(project_dir: &Path) -> Result<Vec<String>> {
+    let trust = read_trust()?;
+    let rules = trust
+        .get(&project_key(project_dir))
+        .and_then(|project| project.get("allow"))
+        .and_then(|allow| allow.as_array())
+        .map(|allow| allow.iter().filter_map(|r| r.as_str().map(str::to_string)).collect())
+        .unwrap_or_default();
+    Ok(rules)
+}
+
+/// Add `rules` to the project's trusted allow rules
+fn record_trust(project_dir: &Path, rules: &[String]) -> Result<()> {
+    let path = trust_path().context("No config directory to record trusted rules in")?;
+    let mut trust = read_trust()?;
+
+    let project = trust
+        .entry(project_key(project_dir))
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+        .as_table_mut()
+        .with_context(|| format!("Malformed entry in {}", path.display()))?;
+    let allow = project
+        .entry("allow")
+        .or_insert_with(|| toml::Value::Array(Vec::new()))
+        .as_array_mut()
+        .with_context(|| format!("Malformed entry in {}", path.display()))?;
+    for rule in rules {
+        if !allow.iter().any(|r| r.as_str() == Some(rule)) {
+            allow.push(toml::Value::String(rule.clone()));
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, toml::to_string_pretty(&trust)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// A rule that matched a call
+enum Lookup {
+    Deny(String),
+    Ask,
+    Allow(Rule),
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct ProjectConfig {
+    #[serde(default)]
+    permissions: PermissionsConfig,
+}
+
+/// What a call acts on: the command for bash, otherwise the path or
+/// pattern argument, with paths made relative to `base` where possible
+fn subject(tool: &str, arguments: &Value, base: &Path) -> String {
+    if let Some(command) = arguments["command"].as_str() {
+        return command.trim().to_string();
+    }
+    if let Some(path) = arguments["path"].as_str() {
+        return relative_path(path, base);
+    }
+    match tool {
+        "list" => arguments["pattern"].as_str().unwrap_or_default().to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Directories output may be redirected into without asking, besides the
+/// working directory
+const SCRATCH_DIRS: &[&str] = &["/tmp", "/var/tmp", "/dev/shm"];
+
+/// Devices output may be redirected to without asking
+const OUTPUT_DEVICES: &[&str] = &["/dev/null", "/dev/stdout", "/dev/stderr", "/dev/tty", "/dev/fd"];
+
+/// The first file `command` redirects output into outside `base` and the
+/// temp directories. Targets that can't be resolved before the command
+/// runs, like `~/x`, `$HOME/x` or relative ones after a `cd`, count as
+/// outside.
+fn outside_write(command: &SimpleCommand, base: &Path, moves: bool) -> Option<String> {
+    let base = base.canonicalize().unwrap_or_else(|_| base.to_path_buf());
+
+    for redirect in &command.redirects {
+        let target = &redirect.target;
+        if !redirect.op.contains('>') {
+            continue;
+        }
+        // `2>&1` and `>&-` duplicate or close descriptors
+        if redirect.op.ends_with(">&") && (target == "-" || target.chars().all(|c| c.is_ascii_digit())) {
+            continue;
+        }
+        if target.contains(['$', '`', '~', '*', '?', '[']) || (moves && !target.starts_with('/')) {
+            return Some(target.clone());
+        }
+
+        let path = base.join(target);
+        if OUTPUT_DEVICES.iter().any(|device| path.starts_with(device)) {
+            continue;
+        }
+        let path = canonicalize_lenient(&path).unwrap_or(path);
+        let inside = path.starts_with(&base)
+            || SCRATCH_DIRS.iter().any(|dir| {
+                let dir = Path::new(dir);
+                path.starts_with(dir) || dir.canonicalize().is_ok_and(|dir| path.starts_with(dir))
+            });
+        if !inside {
+            return Some(target.clone());
+        }
+    }
+    None
+}
+
+fn relative_path(path: &str, base: &Path) -> String {
+    let mut normalized = PathBuf::new();
+    for component in base.join(path).components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            component => normalized.push(component),
+        }
+    }
+
+    match normalized.strip_prefix(base) {
+        Ok(relative) => relative.to_string_lossy().to_string(),
+        Err(_) => normalized.to_string_lossy().to_string(),
+    }
+}
+
+/// The text a pattern matches if it has no wildcards, only metacharacters
+/// escaped as one-character classes like `[*]`
+fn unescape_glob(pattern: &str) -> Option<String> {
+    let mut literal = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => {
+                let escaped = chars.next()?;
+                if chars.next()? != ']' {
+                    return None;
+                }
+                literal.push(escaped);
+            }
+            '\\' | '*' | '?' | ']' | '{' | '}' => return None,
+            c => literal.push(c),
+        }
+    }
+    Some(literal)
+}
+
+/// Make `s` match only itself when used as a rule pattern
+fn escape_glob(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            // A backslash would otherwise escape whatever follows it
+            '\\' | '*' | '?' | '[' | ']' | '{' | '}' => {
+                escaped.push('[');
+                escaped.push(c);
+                escaped.push(']');
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn permissions(mode: PermissionMode, allow: &[&str]) -> Permissions {
+        Permissions::from_config(&PermissionsConfig {
+            mode,
+            allow: allow.iter().map(|r| r.to_string()).collect(),
+            ask: Vec::new(),
+            deny: Vec::new(),
+        })
+        .unwrap()
+    }
+
+    fn bash(permissions: &Permissions, command: &str) -> Decision {
+        let base = Path::new(env!("CARGO_MANIFEST_DIR"));
+        permissions.check("bash", &json!({ "command": command }), base)
+    }
+
+    #[test]
+    fn prefix_rules_allow_writes_inside_the_working_directory() {
+        let permissions = permissions(PermissionMode::Ask, &["bash(echo:*)"]);
+        for command in ["echo hi", "echo hi > out.txt", "echo hi 2>&1 >> logs/out.log", "echo hi > /dev/null"] {
+            assert_eq!(bash(&permissions, command), Decision::Allow, "{}", command);
+        }
+    }
+
+    #[test]
+    fn prefix_rules_ask_before_writing_outside_the_working_directory() {
+        let permissions = permissions(PermissionMode::Ask, &["bash(echo:*)"]);
+        for command in [
+            "echo hi >> /etc/passwd",
+            "echo hi > ~/.bashrc",
+            "echo hi > $HOME/.profile",
+            "echo hi > ../outside.txt",
+            "cd /etc && echo hi > passwd",
+        ] {
+            let Decision::Ask(requests) = bash(&permissions, command) else {
+                panic!("`{}` should need approval", command);
+            };
+            assert!(
+                requests.iter().any(|r| r.reason.as_deref().is_some_and(|r| r.contains("outside the working directory"))),
+                "{}: {:?}",
+                command,
+                requests
+            );
+        }
+    }
+
+    #[test]
+    fn auto_mode_still_asks_before_writing_outside_the_working_directory() {
+        let permissions = permissions(PermissionMode::Auto, &[]);
+        assert!(matches!(bash(&permissions, "echo hi > ~/.bashrc"), Decision::Ask(_)));
+        assert_eq!(bash(&permissions, "echo hi > /tmp/out.txt"), Decision::Allow);
+    }
+
+    #[test]
+    fn exact_rules_cover_their_redirections() {
+        let permissions = permissions(PermissionMode::Ask, &["bash(echo hi >>/etc/motd)"]);
+        assert_eq!(bash(&permissions, "echo hi >> /etc/motd"), Decision::Allow);
+        assert!(matches!(bash(&permissions, "echo hi >> /etc/passwd"), Decision::Ask(_)));
+    }
+
+    #[test]
+    fn approving_a_command_with_braces_allows_exactly_that_command() {
+        let permissions = permissions(PermissionMode::Ask, &[]);
+        let Decision::Ask(requests) = bash(&permissions, "awk '{print $1}' f") else {
+            panic!("awk should need approval");
+        };
+        for request in &requests {
+            assert!(permissions.resolve(request, Approval::Session).unwrap());
+        }
+        assert_eq!(bash(&permissions, "awk '{print $1}' f"), Decision::Allow);
+        assert!(matches!(bash(&permissions, "awk '{print $2}' f"), Decision::Ask(_)));
+
+        let rule = Rule::parse("bash(awk [{]print $1[}] f[*])").unwrap();
+        assert!(matches!(rule.pattern, Some(Pattern::Exact(ref text)) if text == "awk {print $1} f*"));
+    }
+}
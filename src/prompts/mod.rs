@@ -0,0 +1,200 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// An argument declared in a prompt's frontmatter
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptArgument {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Frontmatter {
+    description: Option<String>,
+    #[serde(default)]
+    arguments: Vec<PromptArgument>,
+}
+
+/// A reusable prompt loaded from `<name>.md`
+///
+/// The file may start with TOML frontmatter between `+++` lines declaring a
+/// description and arguments. `{{argument}}` placeholders in the body are
+/// replaced when the prompt is rendered.
+#[derive(Debug, Clone)]
+pub struct PromptTemplate {
+    pub name: String,
+    pub description: Option<String>,
+    pub arguments: Vec<PromptArgument>,
+    pub template: String,
+}
+
+impl PromptTemplate {
+    pub fn parse(name: &str, source: &str) -> Result<Self> {
+        let (frontmatter, body) = match source.strip_prefix("+++") {
+            Some(rest) => {
+                let end = rest
+                    .find("\n+++")
+                    .context("Unterminated '+++' frontmatter")?;
+                let frontmatter: Frontmatter =
+                    toml::from_str(&rest[..end]).context("Invalid prompt frontmatter")?;
+                let body = rest[end + 4..].trim_start_matches(['\r', '\n']);
+                (frontmatter, body)
+            }
+            None => (Frontmatter::default(), source),
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            description: frontmatter.description,
+            arguments: frontmatter.arguments,
+            template: body.to_string(),
+        })
+    }
+
+    /// Substitute arguments into the template
+    pub fn render(&self, args: &HashMap<String, String>) -> Result<String> {
+        for name in args.keys() {
+            if !self.arguments.iter().any(|a| &a.name == name) {
+                anyhow::bail!("Unknown argument '{}' for prompt '{}'", name, self.name);
+            }
+        }
+
+        let mut values = HashMap::new();
+        for arg in &self.arguments {
+            let value = match (args.get(&arg.name), &arg.default) {
+                (Some(value), _) => value.clone(),
+                (None, Some(default)) => default.clone(),
+                (None, None) if arg.required => {
+                    anyhow::bail!(
+                        "Missing required argument '{}' for prompt '{}'",
+                        arg.name,
+                        self.name
+                    )
+                }
+                (None, None) => String::new(),
+            };
+            values.insert(arg.name.as_str(), value);
+        }
+
+        let mut output = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find("{{") {
+            output.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let Some(end) = after.find("}}") else {
+                output.push_str(&rest[start..]);
+                rest = "";
+                break;
+            };
+
+            let key = after[..end].trim();
+            match values.get(key) {
+                Some(value) => output.push_str(value),
+                // Leave unknown placeholders untouched
+                None => output.push_str(&rest[start..start + 2 + end + 2]),
+            }
+            rest = &after[end + 2..];
+        }
+        output.push_str(rest);
+
+        Ok(output)
+    }
+
+    /// Map REPL-style arguments (`key=value` or positional) to named arguments
+    pub fn bind_args(&self, words: &[String]) -> Result<HashMap<String, String>> {
+        let mut args = HashMap::new();
+        let mut positional = self.arguments.iter();
+
+        for word in words {
+            if let Some((key, value)) = word.split_once('=') {
+                if self.arguments.iter().any(|a| a.name == key) {
+                    args.insert(key.to_string(), value.to_string());
+                    continue;
+                }
+            }
+
+            let arg = positional
+                .by_ref()
+                .find(|a| !args.contains_key(&a.name))
+                .with_context(|| format!("Too many arguments for prompt '{}'", self.name))?;
+            args.insert(arg.name.clone(), word.clone());
+        }
+
+        Ok(args)
+    }
+}
+
+/// Prompt templates from the user and project prompt directories
+#[derive(Debug, Default)]
+pub struct PromptLibrary {
+    prompts: BTreeMap<String, PromptTemplate>,
+}
+
+impl PromptLibrary {
+    /// Load `~/.config/aishell/prompts` and then `<workdir>/.aishell/prompts`,
+    /// letting project prompts override user prompts of the same name
+    pub fn load(workdir: &Path) -> Result<Self> {
+        let mut library = Self::default();
+
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        if let Some(config_dir) = dirs::config_dir() {
+            dirs.push(config_dir.join("aishell").join("prompts"));
+        }
+        dirs.push(workdir.join(".aishell").join("prompts"));
+
+        for dir in dirs {
+            library.load_dir(&dir)?;
+        }
+
+        Ok(library)
+    }
+
+    fn load_dir(&mut self, dir: &Path) -> Result<()> {
+        if !dir.is_dir() {
+            return Ok(());
+        }
+
+        let entries = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read prompts directory: {}", dir.display()))?;
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("md") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+
+            let source = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read prompt: {}", path.display()))?;
+            match PromptTemplate::parse(name, &source) {
+                Ok(prompt) => {
+                    self.prompts.insert(name.to_string(), prompt);
+                }
+                Err(e) => tracing::warn!("Skipping prompt {}: {:#}", path.display(), e),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PromptTemplate> {
+        self.prompts.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PromptTemplate> {
+        self.prompts.values()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prompts.is_empty()
+    }
+}
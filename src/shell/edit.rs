@@ -0,0 +1,74 @@
+use anyhow::Result;
+use serde::Deserialize;
+use similar::TextDiff;
+
+/// One replacement of the edit tool
+#[derive(Debug, Clone, Deserialize)]
+pub struct Edit {
+    pub old_string: String,
+    pub new_string: String,
+    /// Replace every occurrence instead of requiring exactly one
+    #[serde(default)]
+    pub replace_all: bool,
+}
+
+/// Apply `edits` in order to `content`, failing without a partial result if
+/// any of them doesn't match. Returns the new content and the number of
+/// replacements made.
+pub fn apply_edits(content: &str, edits: &[Edit]) -> Result<(String, usize)> {
+    if edits.is_empty() {
+        anyhow::bail!("No edits given");
+    }
+
+    let mut content = content.to_string();
+    let mut replacements = 0;
+    for (i, edit) in edits.iter().enumerate() {
+        let label = match edits.len() {
+            1 => String::new(),
+            _ => format!("Edit {}: ", i + 1),
+        };
+        if edit.old_string.is_empty() {
+            anyhow::bail!("{}old_string is empty; use the write tool to create a file", label);
+        }
+        if edit.old_string == edit.new_string {
+            anyhow::bail!("{}old_string and new_string are the same", label);
+        }
+
+        let (old, new) = match_line_endings(&content, edit);
+        let count = content.matches(old.as_str()).count();
+        match count {
+            0 => anyhow::bail!(
+                "{}old_string was not found; it must match the file exactly, including whitespace and without the line numbers shown by read",
+                label
+            ),
+            1 => {}
+            n if !edit.replace_all => anyhow::bail!(
+                "{}old_string matches {} places; include more surrounding lines to make it unique, or set replace_all",
+                label,
+                n
+            ),
+            _ => {}
+        }
+        content = content.replace(old.as_str(), &new);
+        replacements += count;
+    }
+    Ok((content, replacements))
+}
+
+/// The edit's strings with CRLF line endings if the file uses them and the
+/// strings as given don't match
+fn match_line_endings(content: &str, edit: &Edit) -> (String, String) {
+    if !content.contains(edit.old_string.as_str()) && content.contains("\r\n") && !edit.old_string.contains('\r') {
+        return (edit.old_string.replace('\n', "\r\n"), edit.new_string.replace('\n', "\r\n"));
+    }
+    (edit.old_string.clone(), edit.new_string.clone())
+}
+
+/// Unified diff between two versions of a file, with a little context
+pub fn unified_diff(path: &str, old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(2)
+        .header(&format!("a/{}", path), &format!("b/{}", path))
+        .to_string()
+}
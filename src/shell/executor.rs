@@ -1,19 +1,138 @@
 use anyhow::{Context, Result};
-use duct::cmd;
-use std::path::PathBuf;
-use std::time::Duration;
+use serde_json::{json, Value};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::ShellConfig;
+use super::edit::{apply_edits, unified_diff, Edit};
+use super::interpreter::Interpreter;
+use super::interpreter::expand_home;
+use super::journal::Journal;
+use super::list::{list, ListOptions, Listing};
+use super::output::{bound_output, number_lines, OutputLimits, SpillDir};
+use super::patch::{apply_hunks, parse_patch, FilePatch};
+use super::paths::{canonicalize_lenient, SensitivePaths};
+use super::sandbox::Sandbox;
+use super::search::{search, SearchOptions, SearchResults};
+use super::session::PersistentShell;
+
+/// How long to keep collecting output after killing a timed-out command
+const KILL_GRACE: Duration = Duration::from_secs(2);
+
+/// How long to keep collecting output after a command exits while something
+/// it started in the background still holds its output open
+const EXIT_GRACE: Duration = Duration::from_millis(200);
 
 #[derive(Debug)]
 pub struct ExecutionResult {
     pub stdout: String,
     pub stderr: String,
+    /// -1 when the command did not exit normally
     pub exit_code: i32,
     pub success: bool,
+    /// Set to the limit that was exceeded when the command was killed
+    pub timeout: Option<Duration>,
+    /// Signal that terminated the command, e.g. SIGSEGV or an OOM kill's SIGKILL
+    pub signal: Option<i32>,
+    pub core_dumped: bool,
+    /// Wall-clock time from spawn to exit
+    pub duration: Duration,
+    /// Whether stdout or stderr was cut down to fit the output limits
+    pub truncated: bool,
+    /// Why the sandbox seems to have stopped the command
+    pub sandbox_violation: Option<String>,
+}
+
+impl ExecutionResult {
+    pub fn timed_out(&self) -> bool {
+        self.timeout.is_some()
+    }
+
+    /// Conventional name of the terminating signal, e.g. `SIGSEGV`
+    pub fn signal_name(&self) -> Option<String> {
+        self.signal.map(signal_name)
+    }
+
+    /// The result as MCP `structuredContent`
+    pub fn structured(&self) -> Value {
+        json!({
+            "exitCode": (self.signal.is_none() && !self.timed_out()).then_some(self.exit_code),
+            "signal": self.signal_name(),
+            "coreDumped": self.core_dumped,
+            "timedOut": self.timed_out(),
+            "durationMs": self.duration.as_millis() as u64,
+            "truncated": self.truncated,
+            "sandboxViolation": self.sandbox_violation,
+            "stdout": self.stdout,
+            "stderr": self.stderr
+        })
+    }
 }
 
+/// JSON schema of `ExecutionResult::structured`
+pub fn execution_result_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "exitCode": { "type": ["integer", "null"], "description": "Exit code, or null if the command was killed" },
+            "signal": { "type": ["string", "null"], "description": "Signal that terminated the command" },
+            "coreDumped": { "type": "boolean" },
+            "timedOut": { "type": "boolean" },
+            "durationMs": { "type": "integer" },
+            "truncated": { "type": "boolean", "description": "Whether stdout or stderr was shortened" },
+            "sandboxViolation": { "type": ["string", "null"], "description": "Why the sandbox likely blocked the command" },
+            "stdout": { "type": "string" },
+            "stderr": { "type": "string" }
+        },
+        "required": ["exitCode", "signal", "coreDumped", "timedOut", "durationMs", "truncated", "stdout", "stderr"]
+    })
+}
+
+fn signal_name(signal: i32) -> String {
+    let name = match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        _ => return format!("signal {}", signal),
+    };
+    name.to_string()
+}
+
+#[derive(Debug, Clone)]
 pub struct ShellExecutor {
     workdir: PathBuf,
     timeout: Duration,
+    /// Directories file tools may touch; the workdir when empty
+    roots: Vec<PathBuf>,
+    /// Directories file tools may also touch
+    extra_dirs: Vec<PathBuf>,
+    sensitive: SensitivePaths,
+    interpreter: Interpreter,
+    output_limits: OutputLimits,
+    spill: SpillDir,
+    /// Shell that keeps state between commands; a fresh `sh -c` per command when unset
+    shell: Option<PersistentShell>,
+    sandbox: Option<Sandbox>,
+    /// Records what the file tools change, so it can be undone
+    journal: Option<Journal>,
 }
 
 impl ShellExecutor {
@@ -25,32 +144,338 @@ impl ShellExecutor {
         Ok(Self {
             workdir,
             timeout: Duration::from_secs(300), // 5 minutes default
+            roots: Vec::new(),
+            extra_dirs: Vec::new(),
+            sensitive: SensitivePaths::from_config(&ShellConfig::default())?,
+            interpreter: Interpreter::new(None),
+            output_limits: OutputLimits::from_config(&ShellConfig::default()),
+            spill: SpillDir::default(),
+            shell: None,
+            sandbox: None,
+            journal: None,
         })
     }
 
+    pub fn from_config(config: &ShellConfig) -> Result<Self> {
+        let executor = Self::new(config.workdir.clone())?
+            .with_timeout(Duration::from_secs(config.max_execution_time))
+            .with_interpreter(Interpreter::from_config(config))
+            .with_output_limits(OutputLimits::from_config(config))
+            .with_allowed_dirs(config.allowed_dirs.iter().map(|d| expand_home(d)).collect())
+            .with_sensitive_paths(SensitivePaths::from_config(config)?);
+
+        if !config.persistent {
+            return Ok(executor);
+        }
+        if !executor.interpreter.supports_sessions() {
+            tracing::warn!(
+                "Persistent shell sessions are not supported with {}; running each command separately",
+                executor.interpreter.name()
+            );
+            return Ok(executor);
+        }
+        Ok(executor.with_persistent_shell(PersistentShell::new()))
+    }
+
     pub fn with_timeout(mut self, timeout: Duration) -> Self {
         self.timeout = timeout;
         self
     }
 
-    pub fn execute(&self, command: &str) -> Result<ExecutionResult> {
+    pub fn with_interpreter(mut self, interpreter: Interpreter) -> Self {
+        self.interpreter = interpreter;
+        self
+    }
+
+    pub fn with_output_limits(mut self, limits: OutputLimits) -> Self {
+        self.output_limits = limits;
+        self
+    }
+
+    /// Run commands in `shell` so cwd and environment carry over between calls
+    pub fn with_persistent_shell(mut self, shell: PersistentShell) -> Self {
+        self.shell = Some(shell);
+        self
+    }
+
+    /// Let file tools also use `dirs`
+    pub fn with_allowed_dirs(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.extra_dirs = dirs;
+        self
+    }
+
+    pub fn with_sensitive_paths(mut self, sensitive: SensitivePaths) -> Self {
+        self.sensitive = sensitive;
+        self
+    }
+
+    /// Run commands inside `sandbox`, or unconfined when `None`
+    pub fn with_sandbox(mut self, sandbox: Option<Sandbox>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Record the file tools' changes in `journal`
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Confine file tools to `roots`, running commands in the first one
+    pub fn with_roots(mut self, roots: Vec<PathBuf>) -> Self {
+        if let Some(first) = roots.first() {
+            self.workdir = first.clone();
+        }
+        self.roots = roots;
+        self
+    }
+
+    pub fn workdir(&self) -> &Path {
+        &self.workdir
+    }
+
+    /// Where relative paths resolve: the persistent shell's cwd, or the workdir
+    pub fn current_dir(&self) -> PathBuf {
+        self.shell
+            .as_ref()
+            .and_then(|shell| shell.cwd())
+            .unwrap_or_else(|| self.workdir.clone())
+    }
+
+    pub fn persistent_shell(&self) -> Option<&PersistentShell> {
+        self.shell.as_ref()
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    pub fn sensitive_paths(&self) -> &SensitivePaths {
+        &self.sensitive
+    }
+
+    pub fn sandbox(&self) -> Option<&Sandbox> {
+        self.sandbox.as_ref()
+    }
+
+    /// Directories sandboxed commands may write to: the workdir and roots
+    pub(super) fn writable_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = vec![self.workdir.clone()];
+        dirs.extend(self.roots.iter().filter(|r| **r != self.workdir).cloned());
+        dirs
+    }
+
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Apply the output limits to a tool result before it reaches the model
+    pub fn bound_output(&self, output: String) -> String {
+        bound_output(output, self.output_limits, &self.spill)
+    }
+
+    /// Apply the output limits to a command's stdout and stderr separately,
+    /// so the exit status is never cut off
+    pub fn bound_result(&self, mut result: ExecutionResult) -> ExecutionResult {
+        for stream in [&mut result.stdout, &mut result.stderr] {
+            if self.output_limits.exceeded_by(stream) {
+                *stream = self.bound_output(std::mem::take(stream));
+                result.truncated = true;
+            }
+        }
+        result
+    }
+
+    /// Directories the file tools may use: the roots (or the workdir when
+    /// there are none) plus the configured extra directories
+    pub fn allowed_dirs(&self) -> Vec<PathBuf> {
+        let base = match self.roots.is_empty() {
+            true => std::slice::from_ref(&self.workdir),
+            false => self.roots.as_slice(),
+        };
+        base.iter().chain(self.extra_dirs.iter()).cloned().collect()
+    }
+
+    /// Resolve a tool-supplied path against the current directory,
+    /// following `..` and symlinks, and reject it if it ends up outside the
+    /// allowed directories or matches a sensitive path
+    pub fn resolve_path(&self, path: &str) -> Result<PathBuf> {
+        let resolved = canonicalize_lenient(&self.current_dir().join(path))?;
+
+        // Truncated results must stay readable wherever they were spilled
+        if self.spill.contains(&resolved) {
+            return Ok(resolved);
+        }
+
+        let allowed_dirs = self.allowed_dirs();
+        let allowed = allowed_dirs.iter().any(|dir| {
+            let dir = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+            resolved.starts_with(dir)
+        });
+        if !allowed {
+            anyhow::bail!(
+                "Path '{}' resolves to {}, which is outside the allowed directories: {} (add more with shell.allowed_dirs)",
+                path,
+                resolved.display(),
+                allowed_dirs
+                    .iter()
+                    .map(|d| d.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        if let Some(pattern) = self.sensitive.matching(&resolved) {
+            anyhow::bail!(
+                "Path '{}' matches the sensitive path pattern `{}`; add it to shell.allowed_sensitive_paths to allow access",
+                path,
+                pattern
+            );
+        }
+
+        Ok(resolved)
+    }
+
+    /// Run a command to completion, killing it if it exceeds the timeout
+    pub async fn execute(&self, command: &str) -> Result<ExecutionResult> {
+        self.execute_streaming(command, |_| {}, CancellationToken::new())
+            .await
+    }
+
+    /// Run a command, passing output chunks to `on_output` as they arrive
+    /// and killing the command if `cancel` fires or the timeout expires.
+    /// stdout and stderr are read concurrently so neither pipe can fill up
+    /// and stall the command.
+    pub async fn execute_streaming<F>(
+        &self,
+        command: &str,
+        on_output: F,
+        cancel: CancellationToken,
+    ) -> Result<ExecutionResult>
+    where
+        F: FnMut(&str) + Send,
+    {
         tracing::info!("Executing command: {}", command);
 
-        let output = cmd!("sh", "-c", command)
-            .dir(&self.workdir)
-            .stdout_capture()
-            .stderr_capture()
-            .unchecked()
-            .run()
+        let mut result = match &self.shell {
+            Some(shell) => shell.run(command, self, on_output, cancel).await?,
+            None => self.run_once(command, on_output, cancel).await?,
+        };
+        if let Some(sandbox) = &self.sandbox {
+            result.sandbox_violation =
+                sandbox.diagnose(&result, &self.writable_dirs(), &self.current_dir());
+        }
+        Ok(result)
+    }
+
+    async fn run_once<F>(
+        &self,
+        command: &str,
+        mut on_output: F,
+        cancel: CancellationToken,
+    ) -> Result<ExecutionResult>
+    where
+        F: FnMut(&str) + Send,
+    {
+        let started = Instant::now();
+        let mut process = self.interpreter.command(command);
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.apply(&mut process, &self.writable_dirs())?;
+        }
+        let mut child = process
+            .current_dir(&self.workdir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn()
             .context("Failed to execute command")?;
+        let pid = child.id().context("Command exited before it could be tracked")?;
+        let mut guard = ProcessGroupGuard { pid, finished: false };
+
+        let mut stdout_pipe = child.stdout.take().context("Failed to capture stdout")?;
+        let mut stderr_pipe = child.stderr.take().context("Failed to capture stderr")?;
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let mut stdout_buf = [0u8; 8192];
+        let mut stderr_buf = [0u8; 8192];
+        let mut stdout_open = true;
+        let mut stderr_open = true;
+
+        let deadline = tokio::time::sleep(self.timeout);
+        tokio::pin!(deadline);
+        let mut timed_out = false;
+        let mut status = None;
+
+        while stdout_open || stderr_open {
+            tokio::select! {
+                n = stdout_pipe.read(&mut stdout_buf), if stdout_open => {
+                    let n = n.context("Failed to read stdout")?;
+                    if n == 0 {
+                        stdout_open = false;
+                    } else {
+                        stdout.extend_from_slice(&stdout_buf[..n]);
+                        on_output(&String::from_utf8_lossy(&stdout_buf[..n]));
+                    }
+                }
+                n = stderr_pipe.read(&mut stderr_buf), if stderr_open => {
+                    let n = n.context("Failed to read stderr")?;
+                    if n == 0 {
+                        stderr_open = false;
+                    } else {
+                        stderr.extend_from_slice(&stderr_buf[..n]);
+                        on_output(&String::from_utf8_lossy(&stderr_buf[..n]));
+                    }
+                }
+                result = child.wait(), if status.is_none() => {
+                    status = Some(result.context("Failed to wait for command")?);
+                    // Output the command left in the pipes arrives right away;
+                    // anything after that comes from a background process
+                    let grace = tokio::time::Instant::now() + EXIT_GRACE;
+                    if grace < deadline.deadline() {
+                        deadline.as_mut().reset(grace);
+                    }
+                }
+                _ = &mut deadline => {
+                    if timed_out || status.is_some() {
+                        break;
+                    }
+                    tracing::warn!("Command timed out after {}s: {}", self.timeout.as_secs(), command);
+                    kill_process_group(pid);
+                    timed_out = true;
+                    // Keep draining briefly so partial output isn't lost, but
+                    // don't wait on anything that escaped the process group
+                    deadline.as_mut().reset(tokio::time::Instant::now() + KILL_GRACE);
+                }
+                _ = cancel.cancelled() => anyhow::bail!("Command cancelled"),
+            }
+        }
 
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        let exit_code = output.status.code().unwrap_or(-1);
-        let success = output.status.success();
+        let status = match status {
+            Some(status) => status,
+            None => tokio::select! {
+                status = child.wait() => status.context("Failed to wait for command")?,
+                _ = cancel.cancelled() => anyhow::bail!("Command cancelled"),
+            },
+        };
+        guard.finished = true;
 
-        tracing::debug!(
-            "Command result: exit_code={}, stdout_len={}, stderr_len={}",
+        let stdout = String::from_utf8_lossy(&stdout).to_string();
+        let stderr = String::from_utf8_lossy(&stderr).to_string();
+        let exit_code = status.code().unwrap_or(-1);
+        // A timed-out command was killed by us; report the timeout instead
+        let signal = status.signal().filter(|_| !timed_out);
+
+        tracing::info!(
+            "Command finished: exit_code={}, stdout_len={}, stderr_len={}",
             exit_code,
             stdout.len(),
             stderr.len()
@@ -60,48 +485,323 @@ impl ShellExecutor {
             stdout,
             stderr,
             exit_code,
-            success,
+            success: status.success() && !timed_out,
+            timeout: timed_out.then_some(self.timeout),
+            signal,
+            core_dumped: status.core_dumped(),
+            duration: started.elapsed(),
+            truncated: false,
+            sandbox_violation: None,
         })
     }
 
+    /// Read a text file, decoding UTF-16 with a byte order mark and
+    /// refusing binary files and other encodings
     pub fn read_file(&self, path: &str) -> Result<String> {
-        let full_path = self.workdir.join(path);
-        std::fs::read_to_string(&full_path)
-            .with_context(|| format!("Failed to read file: {}", path))
+        self.read_text(path).map(|(text, _)| text)
+    }
+
+    /// Read a text file along with how it is encoded
+    fn read_text(&self, path: &str) -> Result<(String, TextEncoding)> {
+        let full_path = self.resolve_path(path)?;
+        let bytes = std::fs::read(&full_path)
+            .with_context(|| format!("Failed to read file: {}", path))?;
+        decode_text(&bytes).map_err(|e| anyhow::anyhow!("Can't read '{}' as text: {}", path, e))
+    }
+
+    /// Lines of a file from 1-based `offset`, numbered and cut to `limit`
+    /// and the output limits
+    pub fn read_lines(&self, path: &str, offset: usize, limit: Option<usize>) -> Result<String> {
+        let content = self.read_file(path)?;
+        Ok(number_lines(&content, offset.max(1), limit, self.output_limits))
     }
 
     pub fn write_file(&self, path: &str, content: &str) -> Result<()> {
-        let full_path = self.workdir.join(path);
+        self.write_text(path, content, TextEncoding::Utf8)
+    }
+
+    fn write_text(&self, path: &str, content: &str, encoding: TextEncoding) -> Result<()> {
+        let full_path = self.resolve_path(path)?;
+        self.write_resolved(&full_path, Some(&encoding.encode(content)))
+            .with_context(|| format!("Failed to write file: {}", path))
+    }
+
+    /// Write or, given `None`, remove a file whose path was already
+    /// checked, recording the change in the journal
+    fn write_resolved(&self, path: &Path, content: Option<&[u8]>) -> Result<()> {
+        let before = match &self.journal {
+            Some(_) => std::fs::read(path).ok(),
+            None => None,
+        };
 
-        // Create parent directories if needed
-        if let Some(parent) = full_path.parent() {
-            std::fs::create_dir_all(parent)?;
+        write_or_remove(path, content)?;
+
+        if let Some(journal) = &self.journal {
+            journal.record(path, before, content.map(|c| c.to_vec()));
         }
+        Ok(())
+    }
 
-        std::fs::write(&full_path, content)
-            .with_context(|| format!("Failed to write file: {}", path))
+    /// Apply `edits` to a file, writing it only if all of them match, and
+    /// describe the change as a diff. The file keeps its encoding and byte
+    /// order mark.
+    pub fn edit_file(&self, path: &str, edits: &[Edit]) -> Result<String> {
+        let (original, encoding) = self.read_text(path)?;
+        let (edited, replacements) = apply_edits(&original, edits)?;
+        self.write_text(path, &edited, encoding)?;
+
+        Ok(format!(
+            "Edited {} ({} replacement{})\n\n{}",
+            path,
+            replacements,
+            if replacements == 1 { "" } else { "s" },
+            unified_diff(path, &original, &edited).trim_end()
+        ))
+    }
+
+    /// Apply a unified diff, which may add, delete and rename files. Every
+    /// path goes through the same checks as the other file tools, and
+    /// nothing is written unless all files and hunks apply. If writing one
+    /// file fails, the files already written are put back.
+    pub fn apply_patch(&self, patch: &str) -> Result<String> {
+        let mut changes = Vec::new();
+        let mut failures = Vec::new();
+        for file in parse_patch(patch)? {
+            match self.patch_file(&file) {
+                Ok(change) => changes.push(change),
+                Err(e) => {
+                    let path = file.new_path.or(file.old_path).unwrap_or_default();
+                    failures.push(format!("{}: {}", path, e));
+                }
+            }
+        }
+        if !failures.is_empty() {
+            anyhow::bail!("Patch not applied; no files were changed.\n{}", failures.join("\n"));
+        }
+
+        // Each file's contents before it was touched, to put back if a
+        // later write fails
+        let mut written: Vec<(&Path, Option<Vec<u8>>, _)> = Vec::new();
+        let steps = changes.iter().flat_map(|change| {
+            let write = change.write.as_ref().map(|(path, content)| (path, Some(content.as_slice())));
+            let remove = change.remove.as_ref().map(|path| (path, None));
+            write.into_iter().chain(remove)
+        });
+        for (path, content) in steps {
+            let before = std::fs::read(path).ok();
+            if let Err(e) = write_or_remove(path, content) {
+                for (path, before, _) in written.iter().rev() {
+                    if let Err(e) = write_or_remove(path, before.as_deref()) {
+                        tracing::warn!("Failed to roll back {}: {:#}", path.display(), e);
+                    }
+                }
+                anyhow::bail!(
+                    "Failed to update {} ({:#}); the patch was rolled back",
+                    path.display(),
+                    e
+                );
+            }
+            written.push((path, before, content));
+        }
+        if let Some(journal) = &self.journal {
+            for (path, before, after) in written {
+                journal.record(path, before, after.map(|c| c.to_vec()));
+            }
+        }
+
+        let summary: Vec<&str> = changes.iter().flat_map(|c| c.summary.iter().map(String::as_str)).collect();
+        Ok(format!("Applied patch:\n{}", summary.join("\n")))
     }
 
-    pub fn list_files(&self, pattern: Option<&str>) -> Result<Vec<String>> {
-        let pattern = pattern.unwrap_or("*");
+    /// Work out one file's part of a patch without touching the file
+    fn patch_file(&self, file: &FilePatch) -> Result<PatchedFile> {
+        let source = match &file.old_path {
+            Some(path) => {
+                let resolved = self.resolve_path(path)?;
+                if !resolved.is_file() {
+                    anyhow::bail!("file does not exist");
+                }
+                Some((path, resolved))
+            }
+            None => None,
+        };
+        let target = match &file.new_path {
+            Some(path) => Some((path, self.resolve_path(path)?)),
+            None => None,
+        };
 
-        let output = cmd!("sh", "-c", format!("ls -1 {}", pattern))
-            .dir(&self.workdir)
-            .stdout_capture()
-            .stderr_capture()
-            .unchecked()
-            .run()?;
+        let (title, remove) = match (&source, &target) {
+            (Some((old, resolved)), None) => (format!("D {}", old), Some(resolved.clone())),
+            (None, Some((new, resolved))) => {
+                if resolved.exists() {
+                    anyhow::bail!("file already exists");
+                }
+                (format!("A {}", new), None)
+            }
+            (Some((old, from)), Some((new, to))) if from != to => {
+                if to.exists() {
+                    anyhow::bail!("can't rename to {}: file already exists", new);
+                }
+                (format!("R {} -> {}", old, new), Some(from.clone()))
+            }
+            (Some((old, _)), Some(_)) => (format!("M {}", old), None),
+            (None, None) => anyhow::bail!("neither side of the file header names a file"),
+        };
 
-        if !output.status.success() {
-            return Ok(vec![]);
+        // Renamed and modified files keep their encoding
+        let (content, encoding) = match &source {
+            Some((path, _)) => self.read_text(path)?,
+            None => (String::new(), TextEncoding::Utf8),
+        };
+        let patched = apply_hunks(&content, &file.hunks).map_err(|failures| anyhow::anyhow!(failures.join("\n")))?;
+        // A deletion has to remove every line, or it was made against other contents
+        if target.is_none() && !patched.content.is_empty() {
+            anyhow::bail!(
+                "the patch deletes the file, but {} line(s) of it aren't in the patch",
+                patched.content.lines().count()
+            );
         }
 
-        let files = String::from_utf8_lossy(&output.stdout)
-            .lines()
-            .map(|s| s.to_string())
-            .collect();
+        let hunks = file.hunks.len();
+        let mut summary = vec![format!("{} ({} hunk{})", title, hunks, if hunks == 1 { "" } else { "s" })];
+        summary.extend(patched.notes.iter().map(|note| format!("  {}", note)));
+        Ok(PatchedFile {
+            summary,
+            write: target.map(|(_, resolved)| (resolved, encoding.encode(&patched.content))),
+            remove,
+        })
+    }
+
+    /// Where listed and found paths are shown relative to: the directory
+    /// relative tool paths resolve against, normally the workdir
+    fn path_base(&self) -> PathBuf {
+        let dir = self.current_dir();
+        dir.canonicalize().unwrap_or(dir)
+    }
 
-        Ok(files)
+    /// List the entries under `path` (the current directory by default)
+    /// without involving a shell, so the pattern can't run anything
+    pub async fn list_files(&self, path: Option<&str>, options: ListOptions) -> Result<Listing> {
+        let dir = self.resolve_path(path.unwrap_or("."))?;
+        let base = self.path_base();
+        tokio::task::spawn_blocking(move || list(&dir, &options, &base))
+            .await
+            .context("Listing files failed")?
+    }
+
+    /// Search the files under `path` (the current directory by default),
+    /// skipping sensitive files as well as hidden and ignored ones
+    pub async fn search_files(&self, path: Option<&str>, options: SearchOptions) -> Result<SearchResults> {
+        let path = self.resolve_path(path.unwrap_or("."))?;
+        let sensitive = self.sensitive.clone();
+        let base = self.path_base();
+        tokio::task::spawn_blocking(move || search(&path, &options, &sensitive, &base))
+            .await
+            .context("Searching files failed")?
+    }
+}
+
+/// Write `content` to `path`, creating parent directories, or remove the
+/// file when it is `None`
+fn write_or_remove(path: &Path, content: Option<&[u8]>) -> Result<()> {
+    match content {
+        Some(content) => {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, content)?;
+        }
+        None => std::fs::remove_file(path)?,
+    }
+    Ok(())
+}
+
+/// What applying one file's part of a patch will do
+struct PatchedFile {
+    /// `A path (2 hunks)` and notes on hunks applied loosely
+    summary: Vec<String>,
+    write: Option<(PathBuf, Vec<u8>)>,
+    remove: Option<PathBuf>,
+}
+
+/// How a text file's contents were stored, so edits can be written back
+/// the same way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextEncoding {
+    Utf8,
+    /// UTF-8 with a byte order mark
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl TextEncoding {
+    fn encode(self, text: &str) -> Vec<u8> {
+        match self {
+            Self::Utf8 => text.as_bytes().to_vec(),
+            Self::Utf8Bom => [&[0xEF, 0xBB, 0xBF], text.as_bytes()].concat(),
+            Self::Utf16Le => [0xFF, 0xFE]
+                .into_iter()
+                .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+                .collect(),
+            Self::Utf16Be => [0xFE, 0xFF]
+                .into_iter()
+                .chain(text.encode_utf16().flat_map(u16::to_be_bytes))
+                .collect(),
+        }
+    }
+}
+
+/// Decode file contents as text. A NUL byte near the start marks a binary
+/// file, unless a byte order mark says the text is UTF-16.
+fn decode_text(bytes: &[u8]) -> Result<(String, TextEncoding)> {
+    let utf16 = |bytes: &[u8], unit: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|c| unit([c[0], c[1]])).collect();
+        String::from_utf16(&units).context("The file has a UTF-16 byte order mark but isn't valid UTF-16")
+    };
+    if let Some(rest) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+        return Ok((utf16(rest, u16::from_le_bytes)?, TextEncoding::Utf16Le));
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        return Ok((utf16(rest, u16::from_be_bytes)?, TextEncoding::Utf16Be));
+    }
+
+    if bytes[..bytes.len().min(8192)].contains(&0) {
+        anyhow::bail!("it looks like a binary file ({} bytes)", bytes.len());
+    }
+    let (bytes, encoding) = match bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        Some(rest) => (rest, TextEncoding::Utf8Bom),
+        None => (bytes, TextEncoding::Utf8),
+    };
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Ok((text.to_string(), encoding)),
+        Err(e) => anyhow::bail!(
+            "it isn't valid UTF-8 (invalid byte at offset {}); it may use another encoding such as Latin-1 or Shift_JIS",
+            e.valid_up_to()
+        ),
+    }
+}
+
+/// Kills a command's process group if execution is abandoned early, by
+/// cancellation, an error, or the caller dropping the future
+struct ProcessGroupGuard {
+    pid: u32,
+    finished: bool,
+}
+
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            kill_process_group(self.pid);
+        }
+    }
+}
+
+/// Kill a command's whole process group (its pid is also the group id)
+pub(super) fn kill_process_group(pid: u32) {
+    // SAFETY: killpg only sends a signal; a stale group id fails with ESRCH
+    unsafe {
+        libc::killpg(pid as libc::pid_t, libc::SIGKILL);
     }
 }
 
@@ -110,3 +810,51 @@ impl Default for ShellExecutor {
         Self::new(None).expect("Failed to create default ShellExecutor")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An executor in a fresh directory under the system temp dir
+    fn executor(name: &str) -> (ShellExecutor, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("aishell-executor-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        (ShellExecutor::new(Some(dir.clone())).unwrap(), dir)
+    }
+
+    #[test]
+    fn deletes_a_file_whose_contents_match_the_patch() {
+        let (executor, dir) = executor("delete");
+        std::fs::write(dir.join("gone.txt"), "one\ntwo\n").unwrap();
+
+        let result = executor.apply_patch("--- a/gone.txt\n+++ /dev/null\n@@ -1,2 +0,0 @@\n-one\n-two\n");
+        assert!(result.unwrap().contains("D gone.txt"));
+        assert!(!dir.join("gone.txt").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_delete_a_file_with_other_contents() {
+        let (executor, dir) = executor("delete-mismatch");
+        std::fs::write(dir.join("kept.txt"), "one\ntwo\nthree\n").unwrap();
+
+        assert!(executor.apply_patch("--- a/kept.txt\n+++ /dev/null\n@@ -1,2 +0,0 @@\n-one\n-two\n").is_err());
+        assert!(executor.apply_patch("--- a/kept.txt\n+++ /dev/null\n@@ -1,3 +0,0 @@\n-one\n-2\n-three\n").is_err());
+        assert_eq!(std::fs::read_to_string(dir.join("kept.txt")).unwrap(), "one\ntwo\nthree\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reports_the_exit_status_when_a_background_process_holds_the_output() {
+        let executor = ShellExecutor::new(Some(std::env::temp_dir()))
+            .unwrap()
+            .with_timeout(Duration::from_secs(3));
+
+        let result = executor.execute("(sleep 5 &); echo bg; exit 3").await.unwrap();
+        assert_eq!(result.stdout, "bg\n");
+        assert_eq!(result.exit_code, 3);
+        assert!(result.timeout.is_none());
+        assert!(result.duration < Duration::from_secs(3), "{:?}", result.duration);
+    }
+}
@@ -0,0 +1,214 @@
+use std::path::{Path, PathBuf};
+
+use crate::config::{InterpreterConfig, ShellConfig};
+
+/// Shells whose syntax aishell knows how to drive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellKind {
+    Bash,
+    Zsh,
+    Fish,
+    Sh,
+    Other,
+}
+
+impl ShellKind {
+    fn of(program: &str) -> Self {
+        let name = Path::new(program)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(program);
+        match name {
+            "bash" => Self::Bash,
+            "zsh" => Self::Zsh,
+            "fish" => Self::Fish,
+            "sh" | "dash" | "ash" | "ksh" | "mksh" => Self::Sh,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// The program that runs bash tool commands
+#[derive(Debug, Clone)]
+pub struct Interpreter {
+    kind: ShellKind,
+    program: String,
+    /// Arguments placed before the command; `-c` unless a custom argv is set
+    args: Vec<String>,
+    login: bool,
+    rc_file: Option<PathBuf>,
+}
+
+impl Interpreter {
+    pub fn from_config(config: &ShellConfig) -> Self {
+        Self::new(config.interpreter.as_ref())
+            .with_login(config.login)
+            .with_rc_file(config.rc_file.as_deref().map(expand_home))
+    }
+
+    pub fn new(config: Option<&InterpreterConfig>) -> Self {
+        let (program, args) = match config {
+            Some(InterpreterConfig::Program(program)) => (program.clone(), vec!["-c".to_string()]),
+            Some(InterpreterConfig::Argv(argv)) if !argv.is_empty() => {
+                (argv[0].clone(), argv[1..].to_vec())
+            }
+            _ => (Self::default_program(), vec!["-c".to_string()]),
+        };
+
+        Self {
+            kind: ShellKind::of(&program),
+            program,
+            args,
+            login: false,
+            rc_file: None,
+        }
+    }
+
+    /// Start shells as login shells so they read the profile
+    pub fn with_login(mut self, login: bool) -> Self {
+        self.login = login;
+        self
+    }
+
+    /// Source `rc_file` before running commands
+    pub fn with_rc_file(mut self, rc_file: Option<PathBuf>) -> Self {
+        self.rc_file = rc_file;
+        self
+    }
+
+    /// bash when it is installed, since that is what models expect
+    fn default_program() -> String {
+        match find_in_path("bash") {
+            Some(_) => "bash".to_string(),
+            None => "sh".to_string(),
+        }
+    }
+
+    pub fn kind(&self) -> ShellKind {
+        self.kind
+    }
+
+    /// Short name of the shell, e.g. `zsh`
+    pub fn name(&self) -> &str {
+        Path::new(&self.program)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(&self.program)
+    }
+
+    /// Description of the bash tool for this interpreter
+    pub fn tool_description(&self) -> String {
+        let syntax = match self.kind {
+            ShellKind::Bash => "bash syntax (arrays, [[ ]], set -o pipefail) is available",
+            ShellKind::Zsh => "zsh syntax is available",
+            ShellKind::Fish => "use fish syntax, not POSIX sh: `set VAR value`, `$status`, `and`/`or`",
+            ShellKind::Sh => "only POSIX sh syntax is available; avoid bashisms like [[ ]] and arrays",
+            ShellKind::Other => "use that program's syntax",
+        };
+        format!(
+            "Execute a command with {} ({}) and return the output; {}. Use this for running shell commands, git operations, package management, etc.",
+            self.name(),
+            self.argv_display(),
+            syntax
+        )
+    }
+
+    /// A command running `script` to completion
+    pub fn command(&self, script: &str) -> tokio::process::Command {
+        let mut command = tokio::process::Command::new(&self.program);
+        if self.login && self.kind != ShellKind::Other {
+            command.arg("-l");
+        }
+        command.args(&self.args);
+        match self.rc_prelude() {
+            Some(prelude) => command.arg(format!("{}\n{}", prelude, script)),
+            None => command.arg(script),
+        };
+        command
+    }
+
+    /// Whether aishell knows enough of this shell's syntax to keep a
+    /// persistent session running in it
+    pub fn supports_sessions(&self) -> bool {
+        self.kind != ShellKind::Other
+    }
+
+    /// A command starting the shell to read commands from stdin
+    pub fn session_command(&self) -> tokio::process::Command {
+        let mut command = tokio::process::Command::new(&self.program);
+        if self.login {
+            command.arg("-l");
+        }
+        command
+    }
+
+    /// Commands to send a new persistent session before the first command
+    pub fn session_prelude(&self) -> Option<String> {
+        self.rc_prelude().map(|prelude| format!("{}\n", prelude))
+    }
+
+    /// A script line that runs `command`, then prints `marker` with the
+    /// exit status and working directory on stdout and the marker alone on
+    /// stderr, so the end of the command's output can be found
+    pub fn session_script(&self, command: &str, marker: &str) -> String {
+        let (eval, status) = match self.kind {
+            // `command` keeps a syntax error in eval from exiting the shell
+            ShellKind::Sh | ShellKind::Bash | ShellKind::Other => ("command eval", "$?"),
+            ShellKind::Zsh => ("eval", "$?"),
+            ShellKind::Fish => ("eval", "$status"),
+        };
+        format!(
+            "{} {} < /dev/null\nprintf '\\n{marker}%d %s\\n' {} \"$PWD\"\nprintf '\\n{marker}\\n' >&2\n",
+            eval,
+            self.quote(command),
+            status,
+        )
+    }
+
+    fn rc_prelude(&self) -> Option<String> {
+        if self.kind == ShellKind::Other {
+            return None;
+        }
+        let rc_file = self.rc_file.as_ref()?;
+        let source = match self.kind {
+            ShellKind::Fish => "source",
+            _ => ".",
+        };
+        Some(format!(
+            "{} {} > /dev/null 2>&1 < /dev/null",
+            source,
+            self.quote(&rc_file.to_string_lossy())
+        ))
+    }
+
+    /// Quote a string as a single word for this shell
+    fn quote(&self, s: &str) -> String {
+        match self.kind {
+            ShellKind::Fish => format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'")),
+            _ => format!("'{}'", s.replace('\'', "'\\''")),
+        }
+    }
+
+    fn argv_display(&self) -> String {
+        let mut argv = vec![self.program.clone()];
+        if self.login && self.kind != ShellKind::Other {
+            argv.push("-l".to_string());
+        }
+        argv.extend(self.args.iter().cloned());
+        argv.join(" ")
+    }
+}
+
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+pub(super) fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
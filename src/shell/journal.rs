@@ -0,0 +1,241 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Longest turn label shown when listing rewind points
+const MAX_LABEL_CHARS: usize = 60;
+
+/// A file's contents before and after one change, `None` when it didn't exist
+#[derive(Debug, Clone)]
+struct FileChange {
+    path: PathBuf,
+    before: Option<Vec<u8>>,
+    after: Option<Vec<u8>>,
+}
+
+/// A state the session can go back to: the start of a turn, or a named
+/// checkpoint, with the file changes made since
+#[derive(Debug, Clone)]
+struct RewindPoint {
+    label: String,
+    checkpoint: bool,
+    /// Length of the conversation when the point was made
+    messages: usize,
+    changes: Vec<FileChange>,
+}
+
+impl RewindPoint {
+    fn changed_files(&self) -> Vec<&Path> {
+        let mut files: Vec<&Path> = Vec::new();
+        for change in &self.changes {
+            if !files.contains(&change.path.as_path()) {
+                files.push(&change.path);
+            }
+        }
+        files
+    }
+}
+
+/// What going back to an earlier state did
+#[derive(Debug, Clone, Default)]
+pub struct Reverted {
+    pub restored: Vec<PathBuf>,
+    /// Files changed since by something else, e.g. a bash command, and left
+    /// alone. Their recorded changes are discarded with the rest.
+    pub conflicts: Vec<PathBuf>,
+    /// Length to cut the conversation back to, if it should be
+    pub messages: Option<usize>,
+}
+
+/// Record of the file tools' changes in a session, shared by clones of an
+/// executor, so that turns can be undone and checkpoints returned to.
+/// Changes made by bash commands are not recorded.
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    points: Arc<Mutex<Vec<RewindPoint>>>,
+}
+
+impl Journal {
+    /// Start recording the changes of a turn for the given user input
+    pub fn begin_turn(&self, input: &str, messages: usize) {
+        let first_line = input.lines().next().unwrap_or_default();
+        let mut label: String = first_line.chars().take(MAX_LABEL_CHARS).collect();
+        if label.len() < first_line.len() {
+            label.push_str("...");
+        }
+        self.push(label, false, messages);
+    }
+
+    /// Mark the current state so it can be rewound to later. Returns the
+    /// checkpoint's name, numbered if none was given.
+    pub fn checkpoint(&self, name: Option<&str>, messages: usize) -> String {
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => {
+                let count = self.points.lock().unwrap().iter().filter(|p| p.checkpoint).count();
+                format!("checkpoint {}", count + 1)
+            }
+        };
+        self.push(name.clone(), true, messages);
+        name
+    }
+
+    fn push(&self, label: String, checkpoint: bool, messages: usize) {
+        self.points.lock().unwrap().push(RewindPoint {
+            label,
+            checkpoint,
+            messages,
+            changes: Vec::new(),
+        });
+    }
+
+    pub(super) fn record(&self, path: &Path, before: Option<Vec<u8>>, after: Option<Vec<u8>>) {
+        if before == after {
+            return;
+        }
+        let mut points = self.points.lock().unwrap();
+        if points.is_empty() {
+            points.push(RewindPoint {
+                label: "start of session".to_string(),
+                checkpoint: false,
+                messages: 1,
+                changes: Vec::new(),
+            });
+        }
+        let point = points.last_mut().expect("a point was just added");
+        point.changes.push(FileChange {
+            path: path.to_path_buf(),
+            before,
+            after,
+        });
+    }
+
+    /// The rewind points, numbered for `rewind`, with the files changed
+    /// after each one
+    pub fn describe(&self) -> String {
+        let points = self.points.lock().unwrap();
+        if points.is_empty() {
+            return "No turns or checkpoints yet".to_string();
+        }
+
+        points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| {
+                let kind = if point.checkpoint { "checkpoint" } else { "turn" };
+                let files = point.changed_files();
+                let changed = match files.len() {
+                    0 => String::new(),
+                    _ => format!(
+                        " -> {}",
+                        files.iter().map(|f| f.display().to_string()).collect::<Vec<_>>().join(", ")
+                    ),
+                };
+                format!("{:>3}. {}: {}{}", i + 1, kind, point.label, changed)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Revert the file changes of the most recent turn, keeping the
+    /// conversation. `None` if that turn changed no files or was already
+    /// undone; earlier turns are left to `rewind`.
+    pub fn undo(&self) -> Result<Option<Reverted>> {
+        let mut points = self.points.lock().unwrap();
+        let Some(point) = points.iter_mut().rev().find(|p| !p.checkpoint) else {
+            return Ok(None);
+        };
+        if point.changes.is_empty() {
+            return Ok(None);
+        }
+
+        let reverted = revert(&point.changes)?;
+        point.changes.clear();
+        Ok(Some(reverted))
+    }
+
+    /// Go back to the state at rewind point `n` (1-based, as listed by
+    /// `describe`), reverting every file change made since. A checkpoint
+    /// stays so it can be rewound to again; a turn is dropped.
+    pub fn rewind(&self, n: usize) -> Result<Reverted> {
+        let mut points = self.points.lock().unwrap();
+        if n == 0 || n > points.len() {
+            anyhow::bail!("No rewind point {}; there are {}", n, points.len());
+        }
+
+        let changes: Vec<FileChange> = points[n - 1..].iter().flat_map(|p| p.changes.clone()).collect();
+        let mut reverted = revert(&changes)?;
+        reverted.messages = Some(points[n - 1].messages);
+
+        let keep = if points[n - 1].checkpoint { n } else { n - 1 };
+        points.truncate(keep);
+        if let Some(point) = points.get_mut(n - 1) {
+            point.changes.clear();
+        }
+        Ok(reverted)
+    }
+}
+
+/// Undo `changes` newest first, skipping files whose contents no longer
+/// match what the change left behind
+fn revert(changes: &[FileChange]) -> Result<Reverted> {
+    let mut reverted = Reverted::default();
+    for change in changes.iter().rev() {
+        let current = std::fs::read(&change.path).ok();
+        if current != change.after {
+            if !reverted.conflicts.contains(&change.path) {
+                reverted.conflicts.push(change.path.clone());
+            }
+            continue;
+        }
+
+        match &change.before {
+            Some(content) => {
+                if let Some(parent) = change.path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(&change.path, content)
+                    .with_context(|| format!("Failed to restore {}", change.path.display()))?;
+            }
+            None => std::fs::remove_file(&change.path)
+                .with_context(|| format!("Failed to remove {}", change.path.display()))?,
+        }
+        if !reverted.restored.contains(&change.path) {
+            reverted.restored.push(change.path.clone());
+        }
+    }
+    Ok(reverted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_only_reverts_the_most_recent_turn() {
+        let dir = std::env::temp_dir().join(format!("aishell-journal-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("notes.txt");
+        std::fs::write(&file, "edited").unwrap();
+
+        let journal = Journal::default();
+        journal.begin_turn("edit the notes", 1);
+        journal.record(&file, Some(b"original".to_vec()), Some(b"edited".to_vec()));
+        journal.begin_turn("just answer a question", 3);
+
+        assert!(journal.undo().unwrap().is_none());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "edited");
+
+        journal.begin_turn("edit them again", 5);
+        journal.record(&file, Some(b"edited".to_vec()), Some(b"edited twice".to_vec()));
+        std::fs::write(&file, "edited twice").unwrap();
+
+        let reverted = journal.undo().unwrap().unwrap();
+        assert_eq!(reverted.restored, vec![file.clone()]);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "edited");
+        assert!(journal.undo().unwrap().is_none());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "edited");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
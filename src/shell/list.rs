@@ -0,0 +1,181 @@
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobMatcher};
+use ignore::WalkBuilder;
+use std::path::Path;
+
+use super::paths::display_path;
+use std::time::SystemTime;
+
+/// Entries returned when the call doesn't set a limit
+pub const DEFAULT_LIMIT: usize = 200;
+
+/// Arguments of the list tool
+#[derive(Debug, Clone)]
+pub struct ListOptions {
+    /// Glob relative to the listed directory; `*` stays within one
+    /// directory, `**` crosses them
+    pub pattern: String,
+    pub sort: ListSort,
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListSort {
+    Name,
+    /// Most recently modified first
+    Modified,
+}
+
+impl ListSort {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "name" => Ok(Self::Name),
+            "mtime" | "modified" => Ok(Self::Modified),
+            other => anyhow::bail!("Unknown sort order '{}'; use name or mtime", other),
+        }
+    }
+}
+
+impl Default for ListOptions {
+    fn default() -> Self {
+        Self {
+            pattern: "*".to_string(),
+            sort: ListSort::Name,
+            limit: DEFAULT_LIMIT,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ListEntry {
+    /// Relative to the directory tool paths resolve against, or absolute
+    /// outside it
+    pub path: String,
+    pub kind: EntryKind,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// Matching entries, up to the limit
+#[derive(Debug, Clone)]
+pub struct Listing {
+    pub entries: Vec<ListEntry>,
+    /// How many entries matched in all
+    pub total: usize,
+}
+
+impl Listing {
+    /// One entry per line with its type and size, then a note if some
+    /// entries were left out
+    pub fn format(&self) -> String {
+        if self.entries.is_empty() {
+            return "No matching files".to_string();
+        }
+
+        let mut lines: Vec<String> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let (kind, size, suffix) = match entry.kind {
+                    EntryKind::File => ("file", format_size(entry.size), ""),
+                    EntryKind::Dir => ("dir", "-".to_string(), "/"),
+                    EntryKind::Symlink => ("link", "-".to_string(), ""),
+                };
+                format!("{:<4} {:>7}  {}{}", kind, size, entry.path, suffix)
+            })
+            .collect();
+
+        if self.total > self.entries.len() {
+            lines.push(format!(
+                "... {} more entries not shown; narrow the pattern or raise the limit",
+                self.total - self.entries.len()
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+/// List the entries under `dir` matching the options, skipping hidden and
+/// `.gitignore`d files. Paths are shown relative to `base`.
+pub fn list(dir: &Path, options: &ListOptions, base: &Path) -> Result<Listing> {
+    if options.pattern.starts_with(['/', '~']) || options.pattern.split('/').any(|c| c == "..") {
+        anyhow::bail!(
+            "Pattern '{}' must be relative; use the path argument to list another directory",
+            options.pattern
+        );
+    }
+    if !dir.is_dir() {
+        anyhow::bail!("Not a directory: {}", dir.display());
+    }
+    let glob = compile(&options.pattern)?;
+
+    // Without `**` a pattern can't match deeper than its own components
+    let depth = match options.pattern.contains("**") {
+        true => None,
+        false => Some(options.pattern.split('/').count()),
+    };
+
+    let mut entries = Vec::new();
+    for entry in WalkBuilder::new(dir).max_depth(depth).require_git(false).build() {
+        let Ok(entry) = entry else { continue };
+        let Ok(relative) = entry.path().strip_prefix(dir) else { continue };
+        if relative.as_os_str().is_empty() || !glob.is_match(relative) {
+            continue;
+        }
+        let Ok(metadata) = entry.path().symlink_metadata() else { continue };
+
+        let kind = if metadata.file_type().is_symlink() {
+            EntryKind::Symlink
+        } else if metadata.is_dir() {
+            EntryKind::Dir
+        } else {
+            EntryKind::File
+        };
+        entries.push(ListEntry {
+            path: display_path(entry.path(), base),
+            kind,
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+
+    match options.sort {
+        ListSort::Name => entries.sort_by(|a, b| a.path.cmp(&b.path)),
+        ListSort::Modified => entries.sort_by_key(|e| std::cmp::Reverse(e.modified)),
+    }
+
+    let total = entries.len();
+    entries.truncate(options.limit);
+    Ok(Listing { entries, total })
+}
+
+fn compile(pattern: &str) -> Result<GlobMatcher> {
+    let pattern = pattern.trim_start_matches("./");
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map(|glob| glob.compile_matcher())
+        .with_context(|| format!("Invalid glob pattern: {}", pattern))
+}
+
+/// Size in a short human-readable form, e.g. `12.3K`
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", size, UNITS[unit])
+}
@@ -1,5 +1,25 @@
+pub mod edit;
 pub mod executor;
+pub mod interpreter;
+pub mod journal;
+pub mod list;
+pub mod output;
+pub mod parser;
+pub mod patch;
+pub mod paths;
+pub mod sandbox;
+pub mod search;
+pub mod session;
 pub mod tools;
 
-pub use executor::{ShellExecutor, ExecutionResult};
-pub use tools::{get_tool_definitions, execute_tool, ToolArguments};
+pub use edit::Edit;
+pub use executor::{execution_result_schema, ShellExecutor, ExecutionResult};
+pub use interpreter::{Interpreter, ShellKind};
+pub use journal::{Journal, Reverted};
+pub use list::{ListOptions, ListSort, Listing};
+pub use output::OutputLimits;
+pub use paths::SensitivePaths;
+pub use sandbox::Sandbox;
+pub use search::{search_results_schema, SearchOptions, SearchResults};
+pub use session::PersistentShell;
+pub use tools::{get_tool_definitions, execute_tool, execute_bash_streaming, execute_search, bash_tool_result, format_execution_result, is_read_only_tool, ToolArguments};
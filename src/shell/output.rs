@@ -0,0 +1,200 @@
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+
+use crate::config::ShellConfig;
+
+/// Size limits for tool results sent to the model; zero disables a limit
+#[derive(Debug, Clone, Copy)]
+pub struct OutputLimits {
+    pub max_lines: usize,
+    pub max_bytes: usize,
+}
+
+impl OutputLimits {
+    pub fn from_config(config: &ShellConfig) -> Self {
+        Self {
+            max_lines: config.max_output_lines,
+            max_bytes: config.max_output_bytes,
+        }
+    }
+
+    pub fn exceeded_by(&self, text: &str) -> bool {
+        (self.max_lines > 0 && text.lines().count() > self.max_lines)
+            || (self.max_bytes > 0 && text.len() > self.max_bytes)
+    }
+}
+
+/// A private temp directory holding the full text of truncated results,
+/// shared by clones of an executor and removed when the last one is dropped
+#[derive(Debug, Clone, Default)]
+pub struct SpillDir {
+    dir: Arc<Mutex<Option<TempDir>>>,
+}
+
+impl SpillDir {
+    pub fn path(&self) -> Option<PathBuf> {
+        self.dir.lock().unwrap().as_ref().map(|d| d.path().to_path_buf())
+    }
+
+    /// Whether `path` is a spill file the model may read back
+    pub fn contains(&self, path: &Path) -> bool {
+        match self.path().and_then(|dir| dir.canonicalize().ok()) {
+            Some(dir) => path.starts_with(dir),
+            None => false,
+        }
+    }
+
+    fn save(&self, text: &str) -> Result<PathBuf> {
+        let mut dir = self.dir.lock().unwrap();
+        if dir.is_none() {
+            *dir = Some(
+                tempfile::Builder::new()
+                    .prefix("aishell-output-")
+                    .tempdir()
+                    .context("Failed to create spill directory")?,
+            );
+        }
+        let dir = dir.as_ref().expect("spill directory was just created");
+
+        let mut file = tempfile::Builder::new()
+            .prefix("output-")
+            .suffix(".txt")
+            .tempfile_in(dir.path())
+            .context("Failed to create spill file")?;
+        file.write_all(text.as_bytes())
+            .context("Failed to write spill file")?;
+        let (_, path) = file.keep().context("Failed to keep spill file")?;
+
+        Ok(path)
+    }
+}
+
+/// Shorten `text` to the head and tail that fit within `limits`, saving the
+/// whole text to a spill file the model can page through with `read`
+pub fn bound_output(text: String, limits: OutputLimits, spill: &SpillDir) -> String {
+    if !limits.exceeded_by(&text) {
+        return text;
+    }
+
+    let lines: Vec<&str> = text.lines().collect();
+    let line_budget = match limits.max_lines {
+        0 => lines.len(),
+        max => max,
+    };
+    let byte_budget = match limits.max_bytes {
+        0 => text.len(),
+        max => max,
+    };
+
+    let head = take_lines(lines.iter().copied(), line_budget.div_ceil(2), byte_budget.div_ceil(2));
+    let tail = take_lines(
+        lines[head.len()..].iter().rev().copied(),
+        line_budget / 2,
+        byte_budget / 2,
+    );
+    let omitted = lines.len() - head.len() - tail.len();
+
+    let location = match spill.save(&text) {
+        Ok(path) => format!(
+            "full output ({} lines, {} bytes) saved to {}; use the read tool with offset and limit to see the rest",
+            lines.len(),
+            text.len(),
+            path.display()
+        ),
+        Err(e) => {
+            tracing::warn!("Failed to save truncated output: {:#}", e);
+            "full output could not be saved".to_string()
+        }
+    };
+
+    let mut output = head.join("\n");
+    output.push_str(&format!("\n\n... [{} lines omitted; {}] ...\n\n", omitted, location));
+    output.push_str(&tail.into_iter().rev().collect::<Vec<_>>().join("\n"));
+    output
+}
+
+/// Take up to `max_lines` lines within `max_bytes`, cutting an oversized
+/// first line rather than returning nothing
+fn take_lines<'a>(
+    lines: impl Iterator<Item = &'a str>,
+    max_lines: usize,
+    max_bytes: usize,
+) -> Vec<&'a str> {
+    let mut taken = Vec::new();
+    let mut bytes = 0;
+
+    for line in lines.take(max_lines) {
+        if bytes + line.len() + 1 > max_bytes {
+            if taken.is_empty() {
+                taken.push(truncate_at_char(line, max_bytes));
+            }
+            break;
+        }
+        bytes += line.len() + 1;
+        taken.push(line);
+    }
+
+    taken
+}
+
+fn truncate_at_char(s: &str, max_bytes: usize) -> &str {
+    let mut end = max_bytes.min(s.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Longest line `number_lines` shows in full
+const MAX_LINE_BYTES: usize = 2000;
+
+/// Lines of `content` from 1-based `offset`, numbered like `cat -n` and cut
+/// to `limit` and the output limits, with a hint where to continue
+pub fn number_lines(content: &str, offset: usize, limit: Option<usize>, limits: OutputLimits) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    if lines.is_empty() {
+        return "(empty file)".to_string();
+    }
+    if offset > lines.len() {
+        return format!("(offset {} is past the end of the file, which has {} lines)", offset, lines.len());
+    }
+
+    // Leave room for the continuation hint so the result isn't spilled
+    let max_lines = match limits.max_lines {
+        0 => usize::MAX,
+        max => max.saturating_sub(2).max(1),
+    };
+    let max_bytes = match limits.max_bytes {
+        0 => usize::MAX,
+        max => max.saturating_sub(100),
+    };
+
+    let mut output = String::new();
+    let mut last = offset - 1;
+    for (i, line) in lines.iter().enumerate().skip(offset - 1).take(limit.unwrap_or(usize::MAX).min(max_lines)) {
+        let line = match line.len() > MAX_LINE_BYTES {
+            true => format!("{}... [line cut at {} bytes]", truncate_at_char(line, MAX_LINE_BYTES), MAX_LINE_BYTES),
+            false => line.to_string(),
+        };
+        let numbered = format!("{:>6}\t{}\n", i + 1, line);
+        if output.len() + numbered.len() > max_bytes && i >= offset {
+            break;
+        }
+        output.push_str(&numbered);
+        last = i + 1;
+    }
+
+    if last < lines.len() {
+        output.push_str(&format!(
+            "\n... [showing lines {}-{} of {}; read again with offset {} to continue]",
+            offset,
+            last,
+            lines.len(),
+            last + 1
+        ));
+    }
+    output.trim_end_matches('\n').to_string()
+}
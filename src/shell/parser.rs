@@ -0,0 +1,491 @@
+use anyhow::Result;
+
+/// A command with its arguments, as the shell would run it
+#[derive(Debug, Clone, Default)]
+pub struct SimpleCommand {
+    /// `NAME=value` words before the command name
+    pub assignments: Vec<String>,
+    /// The command name and arguments, with quotes removed. Expansions such
+    /// as `$HOME` and `$(...)` are kept as written.
+    pub words: Vec<String>,
+    pub redirects: Vec<Redirect>,
+    /// Command lists nested in `$(...)`, backticks, `<(...)` or a `( ... )`
+    /// subshell
+    pub nested: Vec<Vec<Pipeline>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    /// The operator, e.g. `>`, `>>`, `2>`, `<<`
+    pub op: String,
+    pub target: String,
+}
+
+/// Commands connected by `|`
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    pub commands: Vec<SimpleCommand>,
+}
+
+impl SimpleCommand {
+    /// The words and redirections as one string, e.g. for matching
+    /// permission rules
+    pub fn text(&self) -> String {
+        let redirects = self.redirects.iter().map(|r| format!("{}{}", r.op, r.target));
+        self.words.iter().cloned().chain(redirects).collect::<Vec<_>>().join(" ")
+    }
+
+    /// The command name, if any
+    pub fn name(&self) -> Option<&str> {
+        self.words.first().map(|w| w.as_str())
+    }
+}
+
+/// Every simple command in `pipelines`, including nested ones
+pub fn simple_commands(pipelines: &[Pipeline]) -> Vec<&SimpleCommand> {
+    let mut commands = Vec::new();
+    for pipeline in pipelines {
+        for command in &pipeline.commands {
+            // A bare `> file` still writes the file
+            if !command.words.is_empty() || !command.redirects.is_empty() {
+                commands.push(command);
+            }
+            for nested in &command.nested {
+                commands.extend(simple_commands(nested));
+            }
+        }
+    }
+    commands
+}
+
+/// Split a command line into pipelines of simple commands. This covers
+/// quoting, `;`/`&&`/`||`/`&` lists, pipes, subshells, command and process
+/// substitution, redirections and here-documents; compound commands like
+/// `if` and `while` are flattened into the commands they contain.
+pub fn parse(input: &str) -> Result<Vec<Pipeline>> {
+    let mut parser = Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+        heredocs: Vec::new(),
+    };
+    let pipelines = parser.list(None)?;
+    if parser.pos < parser.chars.len() {
+        anyhow::bail!("Unexpected '{}'", parser.chars[parser.pos]);
+    }
+    Ok(pipelines)
+}
+
+/// Words that start or end compound commands rather than naming a program
+const RESERVED: &[&str] = &[
+    "if", "then", "elif", "else", "fi", "do", "done", "while", "until", "{", "}", "!", "time",
+    "esac",
+];
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// Here-document delimiters whose bodies start after the next newline
+    heredocs: Vec<(String, bool)>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        let matches = s
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.peek_at(i) == Some(c));
+        if matches {
+            self.pos += s.chars().count();
+        }
+        matches
+    }
+
+    fn skip_blanks(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' => self.pos += 1,
+                '\\' if self.peek_at(1) == Some('\n') => self.pos += 2,
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.pos += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Parse pipelines until `close` (or the end of input) is reached
+    fn list(&mut self, close: Option<char>) -> Result<Vec<Pipeline>> {
+        let mut pipelines = Vec::new();
+        let mut pipeline = Pipeline::default();
+
+        loop {
+            self.skip_blanks();
+            match self.peek() {
+                None => {
+                    if let Some(close) = close {
+                        anyhow::bail!("Missing '{}'", close);
+                    }
+                    break;
+                }
+                Some(c) if Some(c) == close => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(')') => anyhow::bail!("Unexpected ')'"),
+                Some('\n') => {
+                    self.pos += 1;
+                    self.skip_heredoc_bodies()?;
+                    finish(&mut pipelines, &mut pipeline);
+                }
+                Some(';') | Some('&') => {
+                    // `;`, `;;`, `&`, `&&`
+                    self.pos += 1;
+                    if matches!(self.peek(), Some(';') | Some('&')) {
+                        self.pos += 1;
+                    }
+                    finish(&mut pipelines, &mut pipeline);
+                }
+                Some('|') if self.peek_at(1) == Some('|') => {
+                    self.pos += 2;
+                    finish(&mut pipelines, &mut pipeline);
+                }
+                Some('|') => {
+                    self.pos += 1;
+                    self.eat("&");
+                }
+                Some(_) => {
+                    if let Some(command) = self.command()? {
+                        pipeline.commands.push(command);
+                    }
+                }
+            }
+        }
+
+        finish(&mut pipelines, &mut pipeline);
+        Ok(pipelines)
+    }
+
+    /// Parse one simple command or subshell
+    fn command(&mut self) -> Result<Option<SimpleCommand>> {
+        let mut command = SimpleCommand::default();
+
+        if self.eat("(") {
+            command.nested.push(self.list(Some(')'))?);
+        }
+
+        loop {
+            self.skip_blanks();
+            let Some(c) = self.peek() else { break };
+            match c {
+                '\n' | ';' | '|' | ')' => break,
+                '&' if self.peek_at(1) != Some('>') => break,
+                '<' | '>' | '&' => self.redirect(&mut command, String::new())?,
+                '(' if self.peek_at(1) == Some(')') => {
+                    // `name() { ...; }` defines a function; its body is
+                    // parsed as the commands that follow
+                    self.pos += 2;
+                    command.words.clear();
+                }
+                '(' => anyhow::bail!("Unexpected '('"),
+                _ => {
+                    let word = self.word(&mut command)?;
+                    // `2>file`: a number directly before a redirection is its fd
+                    if word.chars().all(|c| c.is_ascii_digit())
+                        && matches!(self.peek(), Some('<') | Some('>'))
+                    {
+                        self.redirect(&mut command, word)?;
+                    } else if command.words.is_empty() && is_assignment(&word) {
+                        command.assignments.push(word);
+                    } else if command.words.is_empty() && RESERVED.contains(&word.as_str()) {
+                        continue;
+                    } else {
+                        command.words.push(word);
+                    }
+                }
+            }
+        }
+
+        // `for x in ...`, `case ... in`, `function f` only introduce other commands
+        if matches!(command.name(), Some("for" | "select" | "case" | "function")) {
+            command.words.clear();
+        }
+
+        let empty = command.words.is_empty()
+            && command.assignments.is_empty()
+            && command.redirects.is_empty()
+            && command.nested.is_empty();
+        Ok((!empty).then_some(command))
+    }
+
+    fn redirect(&mut self, command: &mut SimpleCommand, fd: String) -> Result<()> {
+        let op = ["<<<", "<<-", "&>>", "<<", ">>", "<&", ">&", "&>", "<>", ">|", "<", ">"]
+            .into_iter()
+            .find(|op| self.eat(op))
+            .expect("called at a redirection operator");
+
+        // Process substitution: `<(cmd)` and `>(cmd)`
+        if (op == "<" || op == ">") && self.peek() == Some('(') && fd.is_empty() {
+            let start = self.pos - 1;
+            self.pos += 1;
+            command.nested.push(self.list(Some(')'))?);
+            command.words.push(self.chars[start..self.pos].iter().collect());
+            return Ok(());
+        }
+
+        self.skip_blanks();
+        let target = self.word(command)?;
+        if target.is_empty() {
+            anyhow::bail!("Missing target for '{}'", op);
+        }
+        if op == "<<" || op == "<<-" {
+            self.heredocs.push((target.clone(), op == "<<-"));
+        }
+
+        command.redirects.push(Redirect {
+            op: format!("{}{}", fd, op),
+            target,
+        });
+        Ok(())
+    }
+
+    /// Read one word, removing quotes and collecting nested command lists
+    fn word(&mut self, command: &mut SimpleCommand) -> Result<String> {
+        let mut word = String::new();
+
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\n' | ';' | '&' | '|' | '<' | '>' | '(' | ')' => break,
+                '\\' => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some('\n') => self.pos += 1,
+                        Some(c) => {
+                            word.push(c);
+                            self.pos += 1;
+                        }
+                        None => {}
+                    }
+                }
+                '\'' => {
+                    self.pos += 1;
+                    loop {
+                        match self.peek() {
+                            None => anyhow::bail!("Unterminated single quote"),
+                            Some('\'') => break,
+                            Some(c) => word.push(c),
+                        }
+                        self.pos += 1;
+                    }
+                    self.pos += 1;
+                }
+                '"' => {
+                    self.pos += 1;
+                    loop {
+                        match self.peek() {
+                            None => anyhow::bail!("Unterminated double quote"),
+                            Some('"') => break,
+                            Some('\\') => {
+                                self.pos += 1;
+                                match self.peek() {
+                                    Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                                    Some('\n') => {}
+                                    Some(c) => {
+                                        word.push('\\');
+                                        word.push(c);
+                                    }
+                                    None => anyhow::bail!("Unterminated double quote"),
+                                }
+                                self.pos += 1;
+                            }
+                            Some('$') | Some('`') => self.expansion(&mut word, command)?,
+                            Some(c) => {
+                                word.push(c);
+                                self.pos += 1;
+                            }
+                        }
+                    }
+                    self.pos += 1;
+                }
+                '$' | '`' => self.expansion(&mut word, command)?,
+                c => {
+                    word.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+
+        Ok(word)
+    }
+
+    /// Copy a `$...` or backtick expansion into `word` as written, parsing
+    /// any command substitution into `command.nested`
+    fn expansion(&mut self, word: &mut String, command: &mut SimpleCommand) -> Result<()> {
+        let start = self.pos;
+
+        if self.eat("$((") {
+            self.balanced('(', ')', 2, command)?;
+        } else if self.eat("$(") {
+            command.nested.push(self.list(Some(')'))?);
+        } else if self.eat("${") {
+            self.balanced('{', '}', 1, command)?;
+        } else if self.eat("`") {
+            let mut inner = String::new();
+            loop {
+                match self.peek() {
+                    None => anyhow::bail!("Unterminated backquote"),
+                    Some('`') => break,
+                    Some('\\') if matches!(self.peek_at(1), Some('`' | '\\' | '$')) => {
+                        inner.push(self.peek_at(1).unwrap_or_default());
+                        self.pos += 2;
+                        continue;
+                    }
+                    Some(c) => inner.push(c),
+                }
+                self.pos += 1;
+            }
+            self.pos += 1;
+            command.nested.push(parse(&inner)?);
+        } else {
+            self.pos += 1;
+        }
+
+        word.extend(&self.chars[start..self.pos]);
+        Ok(())
+    }
+
+    /// Skip to the close of a bracket whose `depth` openers were consumed,
+    /// still collecting command substitutions inside it, as in
+    /// `${x:-$(cmd)}`
+    fn balanced(
+        &mut self,
+        open: char,
+        close: char,
+        mut depth: usize,
+        command: &mut SimpleCommand,
+    ) -> Result<()> {
+        let mut inner = String::new();
+        while depth > 0 {
+            match self.peek() {
+                None => anyhow::bail!("Missing '{}'", close),
+                Some('$') | Some('`') => {
+                    self.expansion(&mut inner, command)?;
+                    continue;
+                }
+                Some('\\') => self.pos += 1,
+                Some(c) if c == open => depth += 1,
+                Some(c) if c == close => depth -= 1,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    fn skip_heredoc_bodies(&mut self) -> Result<()> {
+        for (delimiter, strip_tabs) in std::mem::take(&mut self.heredocs) {
+            loop {
+                if self.pos >= self.chars.len() {
+                    anyhow::bail!("Here-document '{}' is not terminated", delimiter);
+                }
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.pos += 1;
+                }
+                let line: String = self.chars[start..self.pos].iter().collect();
+                self.eat("\n");
+
+                let line = match strip_tabs {
+                    true => line.trim_start_matches('\t'),
+                    false => line.as_str(),
+                };
+                if line == delimiter {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn finish(pipelines: &mut Vec<Pipeline>, pipeline: &mut Pipeline) {
+    if !pipeline.commands.is_empty() {
+        pipelines.push(std::mem::take(pipeline));
+    }
+}
+
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(input: &str) -> Vec<String> {
+        let pipelines = parse(input).unwrap();
+        simple_commands(&pipelines).iter().map(|c| c.text()).collect()
+    }
+
+    #[test]
+    fn splits_lists_and_pipelines() {
+        assert_eq!(
+            commands("git status && rm -rf build; ls | wc -l"),
+            ["git status", "rm -rf build", "ls", "wc -l"]
+        );
+    }
+
+    #[test]
+    fn keeps_redirections_in_the_text() {
+        assert_eq!(
+            commands("echo hi >> /etc/passwd 2>&1; > out.txt"),
+            ["echo hi >>/etc/passwd 2>&1", ">out.txt"]
+        );
+    }
+
+    #[test]
+    fn collects_command_substitutions() {
+        assert_eq!(commands("echo $(rm -rf ~)"), ["echo $(rm -rf ~)", "rm -rf ~"]);
+        assert_eq!(commands("echo `rm -rf ~`"), ["echo `rm -rf ~`", "rm -rf ~"]);
+    }
+
+    #[test]
+    fn collects_substitutions_inside_parameter_expansions() {
+        assert_eq!(
+            commands("echo ${x:-$(rm -rf ~)}"),
+            ["echo ${x:-$(rm -rf ~)}", "rm -rf ~"]
+        );
+        assert_eq!(
+            commands("echo \"${x:-`rm -rf ~`}\""),
+            ["echo ${x:-`rm -rf ~`}", "rm -rf ~"]
+        );
+        assert_eq!(commands("echo ${x:-${y:-$(whoami)}}")[1], "whoami");
+    }
+
+    #[test]
+    fn collects_substitutions_inside_arithmetic() {
+        assert_eq!(commands("echo $(( $(rm -rf ~) + 1 ))")[1], "rm -rf ~");
+    }
+
+    #[test]
+    fn rejects_unterminated_expansions() {
+        assert!(parse("echo ${x:-$(rm -rf ~)").is_err());
+        assert!(parse("echo $((1 + 2)").is_err());
+    }
+}
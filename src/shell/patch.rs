@@ -0,0 +1,466 @@
+use anyhow::{Context, Result};
+
+/// Context lines at each end of a hunk that may be ignored to place it
+const MAX_FUZZ: usize = 2;
+
+/// Changes to one file in a unified diff
+#[derive(Debug, Clone)]
+pub struct FilePatch {
+    /// `None` when the patch creates the file
+    pub old_path: Option<String>,
+    /// `None` when the patch deletes the file
+    pub new_path: Option<String>,
+    pub hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    /// The `@@ -a,b +c,d @@` line, to name the hunk in errors
+    pub header: String,
+    /// 1-based line where the hunk starts in the old file
+    pub old_start: usize,
+    pub lines: Vec<HunkLine>,
+    /// `\ No newline at end of file` follows the old or the new side
+    pub no_newline_old: bool,
+    pub no_newline_new: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+/// How closely lines must match to place a hunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Strictness {
+    Exact,
+    /// Trailing whitespace may differ
+    TrimEnd,
+    /// Any surrounding whitespace may differ
+    Trim,
+}
+
+impl Strictness {
+    fn eq(self, a: &str, b: &str) -> bool {
+        match self {
+            Self::Exact => a == b,
+            Self::TrimEnd => a.trim_end() == b.trim_end(),
+            Self::Trim => a.trim() == b.trim(),
+        }
+    }
+}
+
+/// The result of applying a file's hunks
+#[derive(Debug, Clone)]
+pub struct Patched {
+    pub content: String,
+    /// Hunks that applied somewhere other than where the diff said
+    pub notes: Vec<String>,
+}
+
+/// Parse a unified diff, as written by `diff -u` or `git diff`, into its
+/// files. Line counts in hunk headers are not trusted, since models often
+/// get them wrong; a hunk ends at the next hunk or file header.
+pub fn parse_patch(patch: &str) -> Result<Vec<FilePatch>> {
+    let lines: Vec<&str> = patch
+        .lines()
+        .filter(|line| !line.starts_with("```"))
+        .collect();
+
+    let mut files: Vec<FilePatch> = Vec::new();
+    // Whether the last file came from a `diff --git` line still waiting
+    // for its `---`/`+++` headers
+    let mut awaiting_headers = false;
+    let mut in_hunk = false;
+    // Bare empty lines, kept as blank context only if the hunk goes on
+    let mut blanks = 0;
+
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+
+        if let Some(paths) = line.strip_prefix("diff --git ") {
+            let (old, new) = split_git_paths(paths);
+            files.push(FilePatch {
+                old_path: Some(old),
+                new_path: Some(new),
+                hunks: Vec::new(),
+            });
+            awaiting_headers = true;
+            in_hunk = false;
+        } else if line.starts_with("--- ") && lines.get(i + 1).is_some_and(|next| next.starts_with("+++ ")) {
+            let old_path = parse_header_path(&line[4..]);
+            let new_path = parse_header_path(&lines[i + 1][4..]);
+            match files.last_mut() {
+                Some(file) if awaiting_headers => {
+                    file.old_path = old_path;
+                    file.new_path = new_path;
+                }
+                _ => files.push(FilePatch {
+                    old_path,
+                    new_path,
+                    hunks: Vec::new(),
+                }),
+            }
+            if files.last().is_some_and(|f| f.old_path.is_none() && f.new_path.is_none()) {
+                anyhow::bail!("Line {}: both sides of the file header are /dev/null", i + 1);
+            }
+            awaiting_headers = false;
+            in_hunk = false;
+            i += 1;
+        } else if line.starts_with("@@") {
+            let file = files
+                .last_mut()
+                .with_context(|| format!("Line {}: hunk without a ---/+++ file header", i + 1))?;
+            file.hunks.push(Hunk {
+                header: line.to_string(),
+                old_start: parse_old_start(line).with_context(|| format!("Line {}: invalid hunk header '{}'", i + 1, line))?,
+                lines: Vec::new(),
+                no_newline_old: false,
+                no_newline_new: false,
+            });
+            awaiting_headers = false;
+            in_hunk = true;
+            blanks = 0;
+        } else if in_hunk {
+            let hunk = files
+                .last_mut()
+                .and_then(|f| f.hunks.last_mut())
+                .expect("in a hunk");
+            let first = line.chars().next();
+            if matches!(first, Some(' ' | '-' | '+')) {
+                hunk.lines.extend((0..blanks).map(|_| HunkLine::Context(String::new())));
+            }
+            if first.is_some() {
+                blanks = 0;
+            }
+            match first {
+                Some(' ') => hunk.lines.push(HunkLine::Context(line[1..].to_string())),
+                Some('-') => hunk.lines.push(HunkLine::Remove(line[1..].to_string())),
+                Some('+') => hunk.lines.push(HunkLine::Add(line[1..].to_string())),
+                // Editors and models often strip the space of blank context
+                // lines, but a blank line may also just separate files
+                None => blanks += 1,
+                Some('\\') => match hunk.lines.last() {
+                    Some(HunkLine::Remove(_)) => hunk.no_newline_old = true,
+                    Some(HunkLine::Add(_)) => hunk.no_newline_new = true,
+                    _ => {
+                        hunk.no_newline_old = true;
+                        hunk.no_newline_new = true;
+                    }
+                },
+                // Anything else ends the hunk, like the next commit's message
+                Some(_) => in_hunk = false,
+            }
+        } else if let Some(file) = files.last_mut().filter(|_| awaiting_headers) {
+            if line.starts_with("new file mode") {
+                file.old_path = None;
+            } else if line.starts_with("deleted file mode") {
+                file.new_path = None;
+            } else if let Some(path) = line.strip_prefix("rename from ") {
+                file.old_path = Some(unquote(path));
+            } else if let Some(path) = line.strip_prefix("rename to ") {
+                file.new_path = Some(unquote(path));
+            }
+        }
+        i += 1;
+    }
+
+    if files.is_empty() {
+        anyhow::bail!("No file changes found; the patch should be a unified diff with ---/+++ headers and @@ hunks");
+    }
+    Ok(files)
+}
+
+/// Apply `hunks` in order to `content`, placing each one nearest to the
+/// line its header names. Hunks whose lines can't be found even when
+/// ignoring whitespace and a little context are described in the error.
+pub fn apply_hunks(content: &str, hunks: &[Hunk]) -> Result<Patched, Vec<String>> {
+    let crlf = content.contains("\r\n");
+    let mut newline_at_end = content.is_empty() || content.ends_with('\n');
+    let lines: Vec<&str> = content.lines().collect();
+
+    let mut output: Vec<String> = Vec::new();
+    let mut notes = Vec::new();
+    let mut failures = Vec::new();
+    let mut cursor = 0;
+    let mut drift: isize = 0;
+
+    for (n, hunk) in hunks.iter().enumerate() {
+        let label = format!("Hunk {} ({})", n + 1, hunk.header);
+        // `-a,0` inserts after line a rather than replacing from it
+        let start = match hunk.lines.iter().all(|line| matches!(line, HunkLine::Add(_))) {
+            true => hunk.old_start,
+            false => hunk.old_start.saturating_sub(1),
+        };
+        let expected = (start as isize + drift).max(0) as usize;
+
+        let Some((position, fuzz, strictness)) = locate(&lines, cursor, expected, &hunk.lines) else {
+            let old: Vec<&str> = hunk
+                .lines
+                .iter()
+                .filter_map(|line| match line {
+                    HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+                    HunkLine::Add(_) => None,
+                })
+                .take(6)
+                .collect();
+            failures.push(format!(
+                "{} did not apply: its lines were not found at or after line {}; expected:\n{}",
+                label,
+                cursor + 1,
+                old.iter().map(|l| format!("    {}", l)).collect::<Vec<_>>().join("\n")
+            ));
+            continue;
+        };
+
+        let (lead, trail) = context_trim(&hunk.lines, fuzz);
+        let body = &hunk.lines[lead..hunk.lines.len() - trail];
+
+        output.extend(lines[cursor..position].iter().map(|l| l.to_string()));
+        let mut at = position;
+        for line in body {
+            match line {
+                // Keep the file's own version of lines matched loosely
+                HunkLine::Context(_) => {
+                    output.push(lines[at].to_string());
+                    at += 1;
+                }
+                HunkLine::Remove(_) => at += 1,
+                HunkLine::Add(text) => output.push(text.clone()),
+            }
+        }
+
+        let intended = start + lead;
+        if position != intended || fuzz > 0 || strictness != Strictness::Exact {
+            let mut how = Vec::new();
+            if position != intended {
+                how.push(format!("offset {:+}", position as isize - intended as isize));
+            }
+            if fuzz > 0 {
+                how.push(format!("fuzz {}, ignoring outer context lines", fuzz));
+            }
+            match strictness {
+                Strictness::TrimEnd => how.push("ignoring trailing whitespace".to_string()),
+                Strictness::Trim => how.push("ignoring indentation".to_string()),
+                Strictness::Exact => {}
+            }
+            notes.push(format!("{} applied at line {} ({})", label, position + 1, how.join(", ")));
+        }
+
+        if at == lines.len() {
+            if hunk.no_newline_new {
+                newline_at_end = false;
+            } else if hunk.no_newline_old {
+                newline_at_end = true;
+            }
+        }
+        drift = position as isize - intended as isize;
+        cursor = at;
+    }
+
+    if !failures.is_empty() {
+        return Err(failures);
+    }
+    output.extend(lines[cursor..].iter().map(|l| l.to_string()));
+
+    let mut content = output.join(if crlf { "\r\n" } else { "\n" });
+    if newline_at_end && !output.is_empty() {
+        content.push_str(if crlf { "\r\n" } else { "\n" });
+    }
+    Ok(Patched { content, notes })
+}
+
+/// Where a hunk's old lines occur at or after `cursor`, closest to
+/// `expected`, trying looser matches only when stricter ones fail
+fn locate(lines: &[&str], cursor: usize, expected: usize, hunk: &[HunkLine]) -> Option<(usize, usize, Strictness)> {
+    for fuzz in 0..=MAX_FUZZ {
+        let (lead, trail) = context_trim(hunk, fuzz);
+        if fuzz > 0 && lead + trail == 0 {
+            break;
+        }
+        let old: Vec<&str> = hunk[lead..hunk.len() - trail]
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect();
+        let expected = expected + lead;
+
+        // A pure insertion goes where the header says, within the file
+        if old.is_empty() {
+            return Some((expected.clamp(cursor, lines.len().max(cursor)), fuzz, Strictness::Exact));
+        }
+        if old.len() > lines.len().saturating_sub(cursor) {
+            continue;
+        }
+        let last = lines.len() - old.len();
+
+        let mut candidates: Vec<usize> = (cursor..=last).collect();
+        candidates.sort_by_key(|&start| start.abs_diff(expected));
+
+        for strictness in [Strictness::Exact, Strictness::TrimEnd, Strictness::Trim] {
+            let matches_at = |start: usize| old.iter().zip(&lines[start..]).all(|(a, b)| strictness.eq(a, b));
+            if let Some(&start) = candidates.iter().find(|&&start| matches_at(start)) {
+                return Some((start, fuzz, strictness));
+            }
+        }
+    }
+    None
+}
+
+/// How many context lines to skip at the start and end of a hunk to
+/// apply it with the given fuzz, never skipping changed lines
+fn context_trim(hunk: &[HunkLine], fuzz: usize) -> (usize, usize) {
+    let is_context = |line: &&HunkLine| matches!(line, HunkLine::Context(_));
+    let lead = hunk.iter().take_while(is_context).count().min(fuzz);
+    let trail = hunk.iter().rev().take_while(is_context).count().min(fuzz);
+    match lead + trail >= hunk.len() {
+        true => (0, 0),
+        false => (lead, trail),
+    }
+}
+
+/// The two paths of a `diff --git a/x b/y` line
+fn split_git_paths(paths: &str) -> (String, String) {
+    match paths.find(" b/") {
+        Some(split) => (strip_prefix(&unquote(&paths[..split])), strip_prefix(&unquote(&paths[split + 1..]))),
+        None => {
+            let (old, new) = paths.split_once(' ').unwrap_or((paths, paths));
+            (strip_prefix(&unquote(old)), strip_prefix(&unquote(new)))
+        }
+    }
+}
+
+/// The path of a `---` or `+++` line, or `None` for /dev/null
+fn parse_header_path(header: &str) -> Option<String> {
+    // `diff -u` appends a tab and a timestamp
+    let path = header.split('\t').next().unwrap_or(header).trim();
+    match path {
+        "/dev/null" => None,
+        path => Some(strip_prefix(&unquote(path))),
+    }
+}
+
+fn strip_prefix(path: &str) -> String {
+    path.strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path)
+        .to_string()
+}
+
+fn unquote(path: &str) -> String {
+    let path = path.trim();
+    path.strip_prefix('"')
+        .and_then(|p| p.strip_suffix('"'))
+        .unwrap_or(path)
+        .to_string()
+}
+
+/// The old start line of `@@ -a,b +c,d @@`
+fn parse_old_start(header: &str) -> Option<usize> {
+    let old = header.split_whitespace().find(|part| part.starts_with('-'))?;
+    old[1..].split(',').next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(content: &str, patch: &str) -> Result<Patched, Vec<String>> {
+        let files = parse_patch(patch).unwrap();
+        apply_hunks(content, &files[0].hunks)
+    }
+
+    fn numbered(count: usize) -> String {
+        (1..=count).map(|n| format!("line {}\n", n)).collect()
+    }
+
+    #[test]
+    fn applies_at_the_stated_line() {
+        let patched = apply(
+            &numbered(5),
+            "--- a/f\n+++ b/f\n@@ -2,3 +2,3 @@\n line 2\n-line 3\n+three\n line 4\n",
+        )
+        .unwrap();
+        assert_eq!(patched.content, "line 1\nline 2\nthree\nline 4\nline 5\n");
+        assert!(patched.notes.is_empty());
+    }
+
+    #[test]
+    fn applies_with_an_offset() {
+        let content = format!("extra\nextra\nextra\n{}", numbered(5));
+        let patched = apply(
+            &content,
+            "--- a/f\n+++ b/f\n@@ -2,3 +2,3 @@\n line 2\n-line 3\n+three\n line 4\n",
+        )
+        .unwrap();
+        assert!(patched.content.contains("line 2\nthree\nline 4\n"));
+        assert_eq!(patched.notes.len(), 1);
+        assert!(patched.notes[0].contains("applied at line 5 (offset +3)"), "{}", patched.notes[0]);
+    }
+
+    #[test]
+    fn picks_the_occurrence_nearest_the_stated_line() {
+        let content = "a\nx\nb\na\nx\nb\na\nx\nb\n";
+        let patched = apply(content, "--- a/f\n+++ b/f\n@@ -4,3 +4,3 @@\n a\n-x\n+y\n b\n").unwrap();
+        assert_eq!(patched.content, "a\nx\nb\na\ny\nb\na\nx\nb\n");
+        assert!(patched.notes.is_empty());
+    }
+
+    #[test]
+    fn carries_the_offset_to_later_hunks() {
+        let content = format!("extra\nextra\n{}", numbered(20));
+        let patched = apply(
+            &content,
+            "--- a/f\n+++ b/f\n@@ -2,3 +2,3 @@\n line 2\n-line 3\n+three\n line 4\n\
+             @@ -15,3 +15,3 @@\n line 15\n-line 16\n+sixteen\n line 17\n",
+        )
+        .unwrap();
+        assert!(patched.content.contains("line 2\nthree\nline 4\n"));
+        assert!(patched.content.contains("line 15\nsixteen\nline 17\n"));
+        assert_eq!(patched.notes.len(), 2);
+        assert!(patched.notes[1].contains("applied at line 17 (offset +2)"), "{}", patched.notes[1]);
+    }
+
+    #[test]
+    fn applies_with_fuzz_when_outer_context_differs() {
+        let patched = apply(
+            &numbered(5),
+            "--- a/f\n+++ b/f\n@@ -1,5 +1,5 @@\n changed 1\n line 2\n-line 3\n+three\n line 4\n changed 5\n",
+        )
+        .unwrap();
+        assert_eq!(patched.content, "line 1\nline 2\nthree\nline 4\nline 5\n");
+        assert!(patched.notes[0].contains("fuzz 1"), "{}", patched.notes[0]);
+    }
+
+    #[test]
+    fn fails_when_more_context_differs_than_fuzz_allows() {
+        let failures = apply(
+            &numbered(5),
+            "--- a/f\n+++ b/f\n@@ -1,5 +1,5 @@\n changed 0\n changed 1\n changed 2\n-line 3\n+three\n line 4\n",
+        )
+        .unwrap_err();
+        assert!(failures[0].contains("did not apply"), "{}", failures[0]);
+    }
+
+    #[test]
+    fn never_fuzzes_away_changed_lines() {
+        let failures = apply(&numbered(5), "--- a/f\n+++ b/f\n@@ -3 +3 @@\n-missing\n+three\n").unwrap_err();
+        assert!(failures[0].contains("did not apply"), "{}", failures[0]);
+    }
+
+    #[test]
+    fn ignores_whitespace_differences_when_needed() {
+        let patched = apply(
+            "fn main() {\n    body();\n}\n",
+            "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n fn main() {\n-  body();\n+    other();\n }\n",
+        )
+        .unwrap();
+        assert_eq!(patched.content, "fn main() {\n    other();\n}\n");
+        assert!(patched.notes[0].contains("ignoring indentation"), "{}", patched.notes[0]);
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobMatcher};
+use std::path::{Path, PathBuf};
+
+use crate::config::ShellConfig;
+use super::interpreter::expand_home;
+
+/// Files the file tools refuse to touch unless explicitly allowed
+#[derive(Debug, Clone, Default)]
+pub struct SensitivePaths {
+    deny: Vec<PathPattern>,
+    allow: Vec<PathPattern>,
+}
+
+/// A file name pattern, checked against every component of a path, or a
+/// path pattern, checked against the path and everything below it
+#[derive(Debug, Clone)]
+struct PathPattern {
+    source: String,
+    name: Option<GlobMatcher>,
+    paths: Vec<GlobMatcher>,
+}
+
+impl SensitivePaths {
+    pub fn from_config(config: &ShellConfig) -> Result<Self> {
+        Self::new(&config.sensitive_paths, &config.allowed_sensitive_paths)
+    }
+
+    pub fn new(deny: &[String], allow: &[String]) -> Result<Self> {
+        Ok(Self {
+            deny: deny.iter().map(|p| PathPattern::parse(p)).collect::<Result<_>>()?,
+            allow: allow.iter().map(|p| PathPattern::parse(p)).collect::<Result<_>>()?,
+        })
+    }
+
+    /// The deny pattern an absolute path matches, if it isn't allowed
+    pub fn matching(&self, path: &Path) -> Option<&str> {
+        if self.allow.iter().any(|p| p.matches(path)) {
+            return None;
+        }
+        self.deny
+            .iter()
+            .find(|p| p.matches(path))
+            .map(|p| p.source.as_str())
+    }
+}
+
+impl PathPattern {
+    fn parse(source: &str) -> Result<Self> {
+        let glob = |pattern: &str| {
+            GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()
+                .map(|g| g.compile_matcher())
+                .with_context(|| format!("Invalid sensitive path pattern: {}", source))
+        };
+
+        if !source.contains('/') {
+            return Ok(Self {
+                source: source.to_string(),
+                name: Some(glob(source)?),
+                paths: Vec::new(),
+            });
+        }
+
+        let mut base = expand_home(Path::new(source));
+        // Match the real location when the pattern names a symlinked path
+        if !source.contains(['*', '?', '[', '{']) {
+            base = base.canonicalize().unwrap_or(base);
+        }
+        let base = base.to_string_lossy().trim_end_matches('/').to_string();
+
+        Ok(Self {
+            source: source.to_string(),
+            name: None,
+            paths: vec![glob(&base)?, glob(&format!("{}/**", base))?],
+        })
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        match &self.name {
+            Some(name) => path.iter().any(|component| name.is_match(component)),
+            None => self.paths.iter().any(|glob| glob.is_match(path)),
+        }
+    }
+}
+
+/// Resolve `..` and symlinks in a path that may not exist yet by
+/// canonicalizing its deepest existing ancestor
+pub fn canonicalize_lenient(path: &Path) -> Result<PathBuf> {
+    let mut existing = path;
+    let mut missing = Vec::new();
+    let resolved = loop {
+        match existing.canonicalize() {
+            Ok(resolved) => break resolved,
+            // A dangling symlink would be followed on write, wherever it points
+            Err(_) if existing.symlink_metadata().is_ok() => {
+                anyhow::bail!("'{}' is a symlink to a missing file", existing.display())
+            }
+            Err(_) => {
+                let name = existing
+                    .file_name()
+                    .with_context(|| format!("Invalid path: {}", path.display()))?;
+                missing.push(name.to_owned());
+                existing = existing
+                    .parent()
+                    .with_context(|| format!("Invalid path: {}", path.display()))?;
+            }
+        }
+    };
+    Ok(missing.iter().rev().fold(resolved, |acc, name| acc.join(name)))
+}
+
+/// `path` relative to `base` when it is inside it, so it can be passed
+/// back to the file tools as is; absolute otherwise
+pub(super) fn display_path(path: &Path, base: &Path) -> String {
+    match path.strip_prefix(base) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative.to_string_lossy().to_string(),
+        _ => path.to_string_lossy().to_string(),
+    }
+}
@@ -0,0 +1,345 @@
+use anyhow::{Context, Result};
+use landlock::{
+    path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreated,
+    RulesetCreatedAttr, RulesetStatus, ABI,
+};
+use seccompiler::{BpfProgram, SeccompAction, SeccompFilter};
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::config::{SandboxConfig, SandboxProfileConfig};
+use super::executor::ExecutionResult;
+use super::interpreter::expand_home;
+
+/// Landlock version whose rights are requested; older kernels enforce what they can
+const LANDLOCK_ABI: ABI = ABI::V3;
+
+/// Writable besides the workdir, so compilers and package managers work
+const TEMP_DIRS: &[&str] = &["/tmp", "/var/tmp", "/dev/shm"];
+
+/// Devices commands may write to
+const DEVICES: &[&str] = &["/dev/null", "/dev/zero", "/dev/full", "/dev/tty", "/dev/ptmx", "/dev/pts"];
+
+/// Uid and gid commands run as when aishell itself runs as root
+const NOBODY: u32 = 65534;
+
+/// Confines bash tool commands with Linux namespaces, Landlock and seccomp:
+/// the filesystem is read-only outside the workdir, secrets in `$HOME` are
+/// hidden, the network is off unless the profile allows it, and commands
+/// run without privileges
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    profile: String,
+    network: bool,
+    writable: Vec<PathBuf>,
+    hidden: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    /// The configured profile, or `None` when it is `off`
+    pub fn from_config(config: &SandboxConfig) -> Result<Option<Self>> {
+        let profile = match (config.profiles.get(&config.profile), config.profile.as_str()) {
+            (Some(profile), _) => profile.clone(),
+            (None, "off") => return Ok(None),
+            (None, "workspace") => SandboxProfileConfig::default(),
+            (None, "network") => SandboxProfileConfig {
+                network: true,
+                ..SandboxProfileConfig::default()
+            },
+            (None, name) => anyhow::bail!(
+                "Unknown sandbox profile '{}'; use off, workspace, network or one from [sandbox.profiles]",
+                name
+            ),
+        };
+        if !cfg!(target_os = "linux") {
+            anyhow::bail!("The sandbox is only available on Linux");
+        }
+
+        Ok(Some(Self {
+            profile: config.profile.clone(),
+            network: profile.network,
+            writable: profile.writable.iter().map(|p| expand_home(p)).collect(),
+            hidden: profile.hidden.iter().map(|p| expand_home(p)).collect(),
+        }))
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    /// Make `command` start inside the sandbox, writable only in `workdirs`
+    /// and the profile's extra directories
+    pub fn apply(&self, command: &mut tokio::process::Command, workdirs: &[PathBuf]) -> Result<()> {
+        let writable: Vec<PathBuf> = workdirs
+            .iter()
+            .cloned()
+            .chain(self.writable.iter().cloned())
+            .chain(TEMP_DIRS.iter().map(PathBuf::from))
+            .collect();
+
+        let mut setup = ChildSetup {
+            namespaces: libc::CLONE_NEWUSER
+                | libc::CLONE_NEWNS
+                | if self.network { 0 } else { libc::CLONE_NEWNET },
+            uid_map: id_map(unsafe { libc::getuid() }),
+            gid_map: id_map(unsafe { libc::getgid() }),
+            hidden: self
+                .hidden
+                .iter()
+                .filter_map(|path| {
+                    let metadata = std::fs::metadata(path).ok()?;
+                    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+                    Some((path, metadata.is_dir()))
+                })
+                .collect(),
+            ruleset: Some(landlock_ruleset(&writable)?),
+            seccomp: seccomp_filter()?,
+        };
+
+        // SAFETY: the closure runs in the forked child before exec. It only
+        // makes system calls on data prepared above, without allocating.
+        unsafe {
+            command.pre_exec(move || setup.enter());
+        }
+        Ok(())
+    }
+
+    /// Explain a failure that looks like the sandbox stopped the command.
+    /// `workdirs` and `cwd` are those the command ran with.
+    pub fn diagnose(
+        &self,
+        result: &ExecutionResult,
+        workdirs: &[PathBuf],
+        cwd: &Path,
+    ) -> Option<String> {
+        if result.success {
+            return None;
+        }
+        let stderr = result.stderr.to_lowercase();
+        let has = |patterns: &[&str]| patterns.iter().any(|p| stderr.contains(p));
+
+        let mut causes = Vec::new();
+        if self.denies_any(&result.stderr, workdirs, cwd) {
+            causes.push("files outside the workdir and temp directories are read-only and secrets in the home directory are hidden");
+        }
+        if has(&["operation not permitted", "must be superuser", "no new privileges", "effective uid is not 0"]) {
+            causes.push("commands run without privileges and can't use sudo, mount or ptrace");
+        }
+        if !self.network
+            && has(&[
+                "network is unreachable",
+                "could not resolve",
+                "temporary failure in name resolution",
+                "name or service not known",
+                "failed to connect",
+                "connection refused",
+            ])
+        {
+            causes.push("network access is off");
+        }
+
+        match causes.is_empty() {
+            true => None,
+            false => Some(format!(
+                "the command was likely blocked by the '{}' sandbox: {}",
+                self.profile,
+                causes.join("; ")
+            )),
+        }
+    }
+
+    /// Whether an access error in `stderr` names a path the sandbox keeps
+    /// from being written, rather than one the user lacks permission for
+    fn denies_any(&self, stderr: &str, workdirs: &[PathBuf], cwd: &Path) -> bool {
+        let writable: Vec<&Path> = workdirs
+            .iter()
+            .chain(&self.writable)
+            .map(|p| p.as_path())
+            .chain(TEMP_DIRS.iter().chain(DEVICES).map(Path::new))
+            .collect();
+
+        stderr
+            .lines()
+            .filter(|line| {
+                let line = line.to_lowercase();
+                line.contains("permission denied") || line.contains("read-only file system")
+            })
+            .flat_map(mentioned_paths)
+            .map(|path| cwd.join(expand_home(Path::new(path))))
+            .any(|path| {
+                self.hidden.iter().any(|h| path.starts_with(h))
+                    || !writable.iter().any(|w| path.starts_with(w))
+            })
+    }
+}
+
+/// Paths named in an error line such as `touch: cannot touch '/etc/x':
+/// Permission denied` or `bash: /etc/x: Read-only file system`
+fn mentioned_paths(line: &str) -> Vec<&str> {
+    let mut paths = Vec::new();
+    let mut rest = line;
+    while let Some(start) = rest.find(['\'', '‘', '`', '"']) {
+        let open = rest[start..].chars().next().unwrap_or_default();
+        let inner = &rest[start + open.len_utf8()..];
+        let Some(end) = inner.find(['\'', '’', '"']) else { break };
+        paths.push(&inner[..end]);
+        rest = &inner[end..];
+        rest = &rest[rest.chars().next().map_or(0, |c| c.len_utf8())..];
+    }
+    if paths.is_empty() {
+        paths.extend(
+            line.split(':')
+                .map(str::trim)
+                .filter(|s| !s.is_empty() && !s.contains(' ') && s.contains('/')),
+        );
+    }
+    paths
+}
+
+/// Everything the child needs to confine itself, prepared before fork
+struct ChildSetup {
+    namespaces: libc::c_int,
+    uid_map: String,
+    gid_map: String,
+    /// Existing paths to cover, and whether each is a directory
+    hidden: Vec<(CString, bool)>,
+    ruleset: Option<RulesetCreated>,
+    seccomp: BpfProgram,
+}
+
+impl ChildSetup {
+    fn enter(&mut self) -> io::Result<()> {
+        // SAFETY: plain system calls on valid, NUL-terminated strings
+        unsafe {
+            check(libc::unshare(self.namespaces))?;
+            write_file(c"/proc/self/setgroups", b"deny")?;
+            write_file(c"/proc/self/uid_map", self.uid_map.as_bytes())?;
+            write_file(c"/proc/self/gid_map", self.gid_map.as_bytes())?;
+
+            // Keep the mounts below out of the parent's mount namespace
+            check(libc::mount(
+                std::ptr::null(),
+                c"/".as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+            for (path, is_dir) in &self.hidden {
+                match is_dir {
+                    true => check(libc::mount(
+                        c"tmpfs".as_ptr(),
+                        path.as_ptr(),
+                        c"tmpfs".as_ptr(),
+                        libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                        std::ptr::null(),
+                    ))?,
+                    false => check(libc::mount(
+                        c"/dev/null".as_ptr(),
+                        path.as_ptr(),
+                        std::ptr::null(),
+                        libc::MS_BIND,
+                        std::ptr::null(),
+                    ))?,
+                }
+            }
+        }
+
+        let ruleset = self.ruleset.take().ok_or_else(|| io::Error::other("sandbox already entered"))?;
+        let status = ruleset.restrict_self().map_err(io::Error::other)?;
+        if status.ruleset == RulesetStatus::NotEnforced {
+            return Err(io::Error::other("Landlock is not enabled in this kernel"));
+        }
+
+        seccompiler::apply_filter(&self.seccomp).map_err(io::Error::other)
+    }
+}
+
+/// Map the caller's id into the new user namespace, as `nobody` for root
+/// so that commands lose root's capabilities when they exec
+fn id_map(id: u32) -> String {
+    let inside = match id {
+        0 => NOBODY,
+        id => id,
+    };
+    format!("{} {} 1", inside, id)
+}
+
+/// Read access everywhere, write access only under `writable`
+fn landlock_ruleset(writable: &[PathBuf]) -> Result<RulesetCreated> {
+    let all = AccessFs::from_all(LANDLOCK_ABI);
+    Ruleset::default()
+        .handle_access(all)?
+        .create()?
+        .add_rules(path_beneath_rules(["/"], AccessFs::from_read(LANDLOCK_ABI)))?
+        .add_rules(path_beneath_rules(writable, all))?
+        .add_rules(path_beneath_rules(DEVICES, all))
+        .context("Failed to set up the sandbox's filesystem rules")
+}
+
+/// Refuse system calls that could undo the sandbox or reach into other
+/// processes or the kernel
+fn seccomp_filter() -> Result<BpfProgram> {
+    let blocked = [
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_userfaultfd,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_kexec_load,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_acct,
+    ];
+    let rules = blocked.into_iter().map(|call| (call, Vec::new())).collect::<BTreeMap<_, _>>();
+
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::EPERM as u32),
+        std::env::consts::ARCH
+            .try_into()
+            .context("The sandbox doesn't support this architecture")?,
+    )
+    .context("Failed to build the sandbox's system call filter")?;
+    filter
+        .try_into()
+        .context("Failed to compile the sandbox's system call filter")
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    match ret {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Write `data` to a file without allocating
+unsafe fn write_file(path: &CStr, data: &[u8]) -> io::Result<()> {
+    let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+    check(fd)?;
+    let written = libc::write(fd, data.as_ptr().cast(), data.len());
+    libc::close(fd);
+    match written {
+        n if n == data.len() as isize => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
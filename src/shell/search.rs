@@ -0,0 +1,286 @@
+use anyhow::{Context, Result};
+use ignore::overrides::OverrideBuilder;
+use ignore::types::TypesBuilder;
+use ignore::WalkBuilder;
+use regex::{Regex, RegexBuilder};
+use serde_json::{json, Value};
+use std::path::Path;
+
+use super::paths::{display_path, SensitivePaths};
+
+/// Matches returned when the call doesn't set a limit
+pub const DEFAULT_LIMIT: usize = 100;
+
+/// Most context lines shown around each match
+const MAX_CONTEXT: usize = 10;
+
+/// Longest line shown; minified files would otherwise flood the output
+const MAX_LINE_CHARS: usize = 500;
+
+/// Files larger than this are skipped
+const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Arguments of the search tool
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    /// Regular expression to look for
+    pub pattern: String,
+    pub case_insensitive: bool,
+    /// Let the pattern span lines, with `.` matching newlines too
+    pub multiline: bool,
+    /// Only search files matching this glob, e.g. `*.rs` or `src/**`
+    pub glob: Option<String>,
+    /// Only search files of this type, e.g. `rust` or `py`
+    pub file_type: Option<String>,
+    /// Lines shown before and after each match
+    pub context: usize,
+    /// Matches skipped before the ones returned, for paging
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl SearchOptions {
+    pub fn new(pattern: impl Into<String>) -> Self {
+        Self {
+            pattern: pattern.into(),
+            case_insensitive: false,
+            multiline: false,
+            glob: None,
+            file_type: None,
+            context: 0,
+            offset: 0,
+            limit: DEFAULT_LIMIT,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchMatch {
+    /// Relative to the directory tool paths resolve against, or absolute
+    /// outside it
+    pub path: String,
+    /// 1-based number of the first matching line
+    pub line: usize,
+    /// The matching line, or lines for a multiline match
+    pub text: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+/// One page of matches
+#[derive(Debug, Clone)]
+pub struct SearchResults {
+    pub matches: Vec<SearchMatch>,
+    /// How many matches there are in all
+    pub total: usize,
+    pub offset: usize,
+}
+
+impl SearchResults {
+    /// Offset of the next page, if there is one
+    pub fn next_offset(&self) -> Option<usize> {
+        let end = self.offset + self.matches.len();
+        (end < self.total).then_some(end)
+    }
+
+    /// One `path:line: text` line per matching line, with context lines
+    /// marked by `-`, then a note if there are more pages
+    pub fn format(&self) -> String {
+        if self.matches.is_empty() {
+            return match self.total {
+                0 => "No matches found".to_string(),
+                total => format!("No matches past offset {}; there are {} in all", self.offset, total),
+            };
+        }
+
+        let mut lines = Vec::new();
+        for m in &self.matches {
+            if self.matches.len() > 1 && (!m.before.is_empty() || !m.after.is_empty()) && !lines.is_empty() {
+                lines.push("--".to_string());
+            }
+            let first_context = m.line - m.before.len();
+            for (i, text) in m.before.iter().enumerate() {
+                lines.push(format!("{}-{}- {}", m.path, first_context + i, text));
+            }
+            for (i, text) in m.text.lines().enumerate() {
+                lines.push(format!("{}:{}: {}", m.path, m.line + i, text));
+            }
+            let after_start = m.line + m.text.lines().count().max(1);
+            for (i, text) in m.after.iter().enumerate() {
+                lines.push(format!("{}-{}- {}", m.path, after_start + i, text));
+            }
+        }
+
+        if let Some(next) = self.next_offset() {
+            lines.push(format!(
+                "... {} more matches not shown; search again with offset {} or narrow the pattern",
+                self.total - next,
+                next
+            ));
+        }
+        lines.join("\n")
+    }
+
+    /// The results as MCP `structuredContent`
+    pub fn structured(&self) -> Value {
+        json!({
+            "matches": self.matches.iter().map(|m| json!({
+                "path": m.path,
+                "line": m.line,
+                "text": m.text,
+                "before": m.before,
+                "after": m.after
+            })).collect::<Vec<_>>(),
+            "total": self.total,
+            "offset": self.offset,
+            "nextOffset": self.next_offset()
+        })
+    }
+}
+
+/// JSON schema of `SearchResults::structured`
+pub fn search_results_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "matches": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string" },
+                        "line": { "type": "integer", "description": "1-based number of the first matching line" },
+                        "text": { "type": "string" },
+                        "before": { "type": "array", "items": { "type": "string" } },
+                        "after": { "type": "array", "items": { "type": "string" } }
+                    },
+                    "required": ["path", "line", "text", "before", "after"]
+                }
+            },
+            "total": { "type": "integer", "description": "Number of matches in all pages" },
+            "offset": { "type": "integer" },
+            "nextOffset": { "type": ["integer", "null"], "description": "Offset of the next page, if any" }
+        },
+        "required": ["matches", "total", "offset", "nextOffset"]
+    })
+}
+
+/// Search the files under `path` (or the file itself), skipping hidden,
+/// `.gitignore`d, binary and sensitive files. Paths are shown relative to
+/// `base`.
+pub fn search(
+    path: &Path,
+    options: &SearchOptions,
+    sensitive: &SensitivePaths,
+    base: &Path,
+) -> Result<SearchResults> {
+    let regex = RegexBuilder::new(&options.pattern)
+        .case_insensitive(options.case_insensitive)
+        .multi_line(options.multiline)
+        .dot_matches_new_line(options.multiline)
+        .build()
+        .map_err(|e| anyhow::anyhow!("Invalid regular expression: {}", e))?;
+
+    let mut walker = WalkBuilder::new(path);
+    walker.require_git(false);
+    if let Some(glob) = &options.glob {
+        let mut overrides = OverrideBuilder::new(path);
+        overrides
+            .add(glob)
+            .with_context(|| format!("Invalid glob pattern: {}", glob))?;
+        walker.overrides(overrides.build()?);
+    }
+    if let Some(file_type) = &options.file_type {
+        let mut types = TypesBuilder::new();
+        types.add_defaults();
+        types.select(file_type);
+        walker.types(
+            types
+                .build()
+                .with_context(|| format!("Unknown file type '{}'", file_type))?,
+        );
+    }
+
+    let mut files = Vec::new();
+    for entry in walker.build() {
+        let Ok(entry) = entry else { continue };
+        if !entry.file_type().is_some_and(|t| t.is_file())
+            || entry.metadata().map_or(true, |m| m.len() > MAX_FILE_SIZE)
+            || sensitive.matching(entry.path()).is_some()
+        {
+            continue;
+        }
+        files.push(entry.into_path());
+    }
+    files.sort();
+
+    let context = options.context.min(MAX_CONTEXT);
+    let mut matches = Vec::new();
+    let mut total = 0;
+    for file in files {
+        let Ok(bytes) = std::fs::read(&file) else { continue };
+        // Binary files would only produce noise
+        if bytes[..bytes.len().min(8192)].contains(&0) {
+            continue;
+        }
+        let Ok(content) = String::from_utf8(bytes) else { continue };
+
+        let shown = display_path(&file, base);
+
+        let lines: Vec<&str> = content.lines().collect();
+        for (start, end) in find_lines(&regex, &content, options.multiline) {
+            if start >= lines.len() {
+                continue;
+            }
+            total += 1;
+            if total <= options.offset || matches.len() >= options.limit {
+                continue;
+            }
+            let end = end.min(lines.len() - 1);
+            let show = |range: &[&str]| range.iter().map(|l| shorten(l)).collect::<Vec<_>>();
+            matches.push(SearchMatch {
+                path: shown.clone(),
+                line: start + 1,
+                text: show(&lines[start..=end]).join("\n"),
+                before: show(&lines[start.saturating_sub(context)..start]),
+                after: show(&lines[end + 1..(end + 1 + context).min(lines.len())]),
+            });
+        }
+    }
+
+    Ok(SearchResults { matches, total, offset: options.offset })
+}
+
+/// 0-based first and last line of each match; a line matching several
+/// times is reported once
+fn find_lines(regex: &Regex, content: &str, multiline: bool) -> Vec<(usize, usize)> {
+    if !multiline {
+        return content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| regex.is_match(line))
+            .map(|(i, _)| (i, i))
+            .collect();
+    }
+
+    let newlines: Vec<usize> = content.match_indices('\n').map(|(i, _)| i).collect();
+    let line_of = |offset: usize| newlines.partition_point(|&i| i < offset);
+    let mut found: Vec<(usize, usize)> = Vec::new();
+    for m in regex.find_iter(content) {
+        let start = line_of(m.start());
+        // A match ending with its newline belongs to the line it ends
+        let end = line_of(m.end().max(m.start() + 1) - 1).max(start);
+        if found.last().is_some_and(|&(_, last)| start <= last) {
+            continue;
+        }
+        found.push((start, end));
+    }
+    found
+}
+
+fn shorten(line: &str) -> String {
+    match line.char_indices().nth(MAX_LINE_CHARS) {
+        Some((cut, _)) => format!("{}...", &line[..cut]),
+        None => line.to_string(),
+    }
+}
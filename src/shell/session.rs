@@ -0,0 +1,400 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::os::unix::process::ExitStatusExt;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout};
+use tokio_util::sync::CancellationToken;
+
+use super::executor::{kill_process_group, ExecutionResult, ShellExecutor};
+use super::interpreter::Interpreter;
+use super::sandbox::Sandbox;
+
+/// A long-lived shell that keeps the working directory, environment and
+/// functions between commands. Clones share the same shell.
+#[derive(Debug, Clone, Default)]
+pub struct PersistentShell {
+    process: Arc<tokio::sync::Mutex<Option<ShellProcess>>>,
+    cwd: Arc<std::sync::Mutex<Option<PathBuf>>>,
+}
+
+#[derive(Debug)]
+struct ShellProcess {
+    child: Child,
+    pid: u32,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    stderr: ChildStderr,
+}
+
+impl Drop for ShellProcess {
+    fn drop(&mut self) {
+        kill_process_group(self.pid);
+    }
+}
+
+impl ShellProcess {
+    async fn spawn(
+        interpreter: &Interpreter,
+        workdir: &Path,
+        sandbox: Option<(&Sandbox, Vec<PathBuf>)>,
+    ) -> Result<Self> {
+        let mut command = interpreter.session_command();
+        if let Some((sandbox, writable)) = sandbox {
+            sandbox.apply(&mut command, &writable)?;
+        }
+        let mut child = command
+            .current_dir(workdir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn()
+            .context("Failed to start shell session")?;
+        let pid = child.id().context("Shell session exited immediately")?;
+
+        let mut process = Self {
+            pid,
+            stdin: child.stdin.take().context("Failed to open shell stdin")?,
+            stdout: child.stdout.take().context("Failed to capture shell stdout")?,
+            stderr: child.stderr.take().context("Failed to capture shell stderr")?,
+            child,
+        };
+        if let Some(prelude) = interpreter.session_prelude() {
+            process
+                .stdin
+                .write_all(prelude.as_bytes())
+                .await
+                .context("Failed to initialize shell session")?;
+        }
+
+        Ok(process)
+    }
+}
+
+impl PersistentShell {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The shell's working directory after the last command, if it is running
+    pub fn cwd(&self) -> Option<PathBuf> {
+        self.cwd.lock().unwrap().clone()
+    }
+
+    /// Kill the shell; the next command starts a fresh one in the workdir
+    pub async fn reset(&self) {
+        if self.process.lock().await.take().is_some() {
+            tracing::info!("Shell session reset");
+        }
+        self.cwd.lock().unwrap().take();
+    }
+
+    /// Run a command in the shell, starting it with the executor's
+    /// interpreter, workdir and sandbox if needed. Timing out, cancelling or
+    /// dropping the future before the command finishes kills the shell,
+    /// losing its state.
+    pub(super) async fn run<F>(
+        &self,
+        command: &str,
+        executor: &ShellExecutor,
+        mut on_output: F,
+        cancel: CancellationToken,
+    ) -> Result<ExecutionResult>
+    where
+        F: FnMut(&str) + Send,
+    {
+        let started = Instant::now();
+        let interpreter = executor.interpreter();
+        let timeout = executor.timeout();
+        let mut slot = self.process.lock().await;
+        if slot.is_none() {
+            let workdir = executor.workdir();
+            tracing::info!("Starting {} session in {}", interpreter.name(), workdir.display());
+            let sandbox = executor.sandbox().map(|s| (s, executor.writable_dirs()));
+            *slot = Some(ShellProcess::spawn(interpreter, workdir, sandbox).await?);
+        }
+        let mut running = RunningCommand {
+            slot,
+            cwd: &self.cwd,
+            finished: false,
+        };
+        let process = running.slot.as_mut().expect("shell session was just started");
+
+        // The command is eval'd so that a syntax error can't swallow the
+        // marker lines, and reads /dev/null so it can't consume our input
+        let marker = format!("__AISHELL_DONE_{}__", uuid::Uuid::new_v4().simple());
+        let script = interpreter.session_script(command, &marker);
+        if let Err(e) = process.stdin.write_all(script.as_bytes()).await {
+            anyhow::bail!("Shell session is no longer running ({}); it will restart on the next command", e);
+        }
+
+        let mut stdout = MarkedStream::new(&marker);
+        let mut stderr = MarkedStream::new(&marker);
+        let mut stdout_buf = [0u8; 8192];
+        let mut stderr_buf = [0u8; 8192];
+
+        let deadline = tokio::time::sleep(timeout);
+        tokio::pin!(deadline);
+
+        // A stream can reach EOF while the shell lives on, when the command
+        // redirects or closes the shell's own stdout or stderr with `exec`
+        let mut stdout_eof = false;
+        let mut stderr_eof = false;
+        let mut timed_out = false;
+        while !(stdout.done && stderr.done) {
+            tokio::select! {
+                n = process.stdout.read(&mut stdout_buf), if !stdout.done => {
+                    match n.context("Failed to read stdout")? {
+                        0 => { stdout_eof = true; stdout.done = true; }
+                        n => stdout.feed(&stdout_buf[..n], &mut on_output),
+                    }
+                }
+                n = process.stderr.read(&mut stderr_buf), if !stderr.done => {
+                    match n.context("Failed to read stderr")? {
+                        0 => { stderr_eof = true; stderr.done = true; }
+                        n => stderr.feed(&stderr_buf[..n], &mut on_output),
+                    }
+                }
+                _ = &mut deadline => timed_out = true,
+                _ = cancel.cancelled() => anyhow::bail!("Command cancelled"),
+            }
+            if timed_out {
+                break;
+            }
+        }
+
+        let exited = stdout_eof && stderr_eof;
+        let (exit_code, signal) = if timed_out {
+            (-1, None)
+        } else if exited {
+            // The command likely ended the shell itself, e.g. with `exit`,
+            // but it may also have closed both streams and kept running
+            tokio::select! {
+                status = process.child.wait() => match status {
+                    Ok(status) => (status.code().unwrap_or(-1), status.signal()),
+                    Err(_) => (-1, None),
+                },
+                _ = &mut deadline => {
+                    timed_out = true;
+                    (-1, None)
+                }
+                _ = cancel.cancelled() => anyhow::bail!("Command cancelled"),
+            }
+        } else {
+            // The shell reports a command killed by signal N as 128 + N
+            match stdout.exit_code.or(stderr.exit_code) {
+                Some(code) if code > 128 && code <= 128 + 64 => (-1, Some(code - 128)),
+                code => (code.unwrap_or(-1), None),
+            }
+        };
+        if timed_out {
+            tracing::warn!("Command timed out after {}s: {}", timeout.as_secs(), command);
+        }
+
+        // Resetting kills the shell's process group
+        let mut stderr_text = stderr.text();
+        if exited || timed_out {
+            running.reset();
+            stderr_text.push_str("\n[shell session ended; cwd and environment were reset]");
+        } else if stdout_eof || stderr_eof {
+            // Later commands' output couldn't be read from this shell
+            running.reset();
+            stderr_text.push_str("\n[the command closed the shell's output, so the shell was restarted; cwd and environment were reset]");
+        } else {
+            *self.cwd.lock().unwrap() = stdout.cwd.clone();
+        }
+        running.finished = true;
+
+        let stdout_text = stdout.text();
+        tracing::info!(
+            "Command finished: exit_code={}, stdout_len={}, stderr_len={}",
+            exit_code,
+            stdout_text.len(),
+            stderr_text.len()
+        );
+
+        Ok(ExecutionResult {
+            stdout: stdout_text,
+            stderr: stderr_text,
+            exit_code,
+            success: exit_code == 0 && signal.is_none() && !timed_out,
+            timeout: timed_out.then_some(timeout),
+            signal,
+            core_dumped: false,
+            duration: started.elapsed(),
+            truncated: false,
+            sandbox_violation: None,
+        })
+    }
+}
+
+/// The shell while it runs a command. If the command is abandoned before it
+/// finishes, by an error, cancellation or the caller dropping the future,
+/// the shell is killed so the next command doesn't run behind it.
+struct RunningCommand<'a> {
+    slot: tokio::sync::MutexGuard<'a, Option<ShellProcess>>,
+    cwd: &'a std::sync::Mutex<Option<PathBuf>>,
+    finished: bool,
+}
+
+impl RunningCommand<'_> {
+    /// Kill the shell; the next command starts a fresh one in the workdir
+    fn reset(&mut self) {
+        self.slot.take();
+        self.cwd.lock().unwrap().take();
+    }
+}
+
+impl Drop for RunningCommand<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.reset();
+        }
+    }
+}
+
+/// Output of one command, read until the end-of-command marker line
+struct MarkedStream<'a> {
+    marker: &'a str,
+    buf: Vec<u8>,
+    /// Bytes already passed to the output callback
+    emitted: usize,
+    /// Where the command's own output ends, once the marker is seen
+    end: Option<usize>,
+    done: bool,
+    exit_code: Option<i32>,
+    cwd: Option<PathBuf>,
+}
+
+impl<'a> MarkedStream<'a> {
+    fn new(marker: &'a str) -> Self {
+        Self {
+            marker,
+            buf: Vec::new(),
+            emitted: 0,
+            end: None,
+            done: false,
+            exit_code: None,
+            cwd: None,
+        }
+    }
+
+    fn feed(&mut self, data: &[u8], on_output: &mut impl FnMut(&str)) {
+        self.buf.extend_from_slice(data);
+
+        let needle = format!("\n{}", self.marker);
+        let found =
+            find(&self.buf[self.emitted..], needle.as_bytes()).map(|pos| pos + self.emitted);
+        // Hold back anything that could be the start of the marker
+        let safe = match found {
+            Some(pos) => pos,
+            None => self.buf.len().saturating_sub(needle.len()),
+        };
+        if safe > self.emitted {
+            on_output(&String::from_utf8_lossy(&self.buf[self.emitted..safe]));
+            self.emitted = safe;
+        }
+
+        let Some(pos) = found else {
+            return;
+        };
+        let rest = &self.buf[pos + needle.len()..];
+        let Some(newline) = rest.iter().position(|&b| b == b'\n') else {
+            return;
+        };
+
+        let status = String::from_utf8_lossy(&rest[..newline]).to_string();
+        if let Some((code, cwd)) = status.split_once(' ') {
+            self.exit_code = code.parse().ok();
+            self.cwd = Some(PathBuf::from(cwd));
+        }
+        self.end = Some(pos);
+        self.done = true;
+    }
+
+    fn text(&self) -> String {
+        let end = self.end.unwrap_or(self.buf.len());
+        String::from_utf8_lossy(&self.buf[..end]).to_string()
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn executor(shell: &PersistentShell) -> ShellExecutor {
+        ShellExecutor::new(Some(std::env::temp_dir()))
+            .unwrap()
+            .with_timeout(Duration::from_secs(2))
+            .with_persistent_shell(shell.clone())
+    }
+
+    async fn run(shell: &PersistentShell, command: &str) -> ExecutionResult {
+        let executor = executor(shell);
+        let run = shell.run(command, &executor, |_| {}, CancellationToken::new());
+        tokio::time::timeout(Duration::from_secs(10), run)
+            .await
+            .expect("the command should not hang")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn keeps_state_between_commands() {
+        let shell = PersistentShell::new();
+        run(&shell, "export AISHELL_TEST=kept").await;
+        let result = run(&shell, "echo $AISHELL_TEST").await;
+        assert_eq!(result.stdout.trim(), "kept");
+        assert!(result.success);
+    }
+
+    #[tokio::test]
+    async fn survives_a_command_that_redirects_the_shells_stderr() {
+        let shell = PersistentShell::new();
+        let result = run(&shell, "exec 2>/dev/null; echo x").await;
+        assert_eq!(result.stdout.trim(), "x");
+        assert!(result.timeout.is_none());
+        assert_eq!(result.exit_code, 0);
+
+        // The shell was restarted, so later commands still report stderr
+        let result = run(&shell, "echo next; echo err >&2").await;
+        assert_eq!(result.stdout.trim(), "next");
+        assert_eq!(result.stderr.trim(), "err");
+    }
+
+    #[tokio::test]
+    async fn survives_a_command_that_closes_the_shells_stderr() {
+        let shell = PersistentShell::new();
+        let result = run(&shell, "exec 2>&-; echo x").await;
+        assert_eq!(result.stdout.trim(), "x");
+        assert!(result.timeout.is_none());
+
+        let result = run(&shell, "echo next").await;
+        assert_eq!(result.stdout.trim(), "next");
+    }
+
+    #[tokio::test]
+    async fn times_out_when_the_command_closes_both_streams() {
+        let shell = PersistentShell::new();
+        let result = run(&shell, "exec >/dev/null 2>&1; sleep 30").await;
+        assert!(result.timed_out());
+
+        let result = run(&shell, "echo next").await;
+        assert_eq!(result.stdout.trim(), "next");
+    }
+
+    #[tokio::test]
+    async fn reports_the_exit_status_when_the_shell_exits() {
+        let shell = PersistentShell::new();
+        let result = run(&shell, "exit 3").await;
+        assert_eq!(result.exit_code, 3);
+        assert!(result.timeout.is_none());
+    }
+}
@@ -1,9 +1,14 @@
 use anyhow::{Context, Result};
 use serde::Deserialize;
 use serde_json::json;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
 use crate::llm::ToolDefinition;
-use super::executor::ShellExecutor;
+use super::edit::Edit;
+use super::executor::{ExecutionResult, ShellExecutor};
+use super::list::{self, ListOptions, ListSort};
+use super::search::{self, SearchOptions, SearchResults};
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "tool", rename_all = "snake_case")]
@@ -11,23 +16,57 @@ pub enum ToolArguments {
     Bash { command: String },
     Read { path: String },
     Write { path: String, content: String },
-    List { pattern: Option<String> },
+    Edit {
+        path: String,
+        old_string: Option<String>,
+        new_string: Option<String>,
+        replace_all: Option<bool>,
+        edits: Option<Vec<Edit>>,
+    },
+    ApplyPatch { patch: String },
+    List {
+        pattern: Option<String>,
+        path: Option<String>,
+        sort: Option<String>,
+        limit: Option<usize>,
+    },
+    Search {
+        pattern: String,
+        path: Option<String>,
+        case_insensitive: Option<bool>,
+        multiline: Option<bool>,
+        glob: Option<String>,
+        #[serde(rename = "type")]
+        file_type: Option<String>,
+        context: Option<usize>,
+        offset: Option<usize>,
+        limit: Option<usize>,
+    },
 }
 
-/// Get all available tool definitions for the LLM
-pub fn get_tool_definitions() -> Vec<ToolDefinition> {
+/// Get all available tool definitions for the LLM, describing the bash tool
+/// in terms of the executor's interpreter
+pub fn get_tool_definitions(executor: &ShellExecutor) -> Vec<ToolDefinition> {
     vec![
         ToolDefinition {
             tool_type: "function".to_string(),
             function: crate::llm::provider::FunctionDefinition {
                 name: "bash".to_string(),
-                description: "Execute a bash command and return the output. Use this for running shell commands, git operations, package management, etc.".to_string(),
+                description: executor.interpreter().tool_description(),
                 parameters: json!({
                     "type": "object",
                     "properties": {
                         "command": {
                             "type": "string",
-                            "description": "The bash command to execute"
+                            "description": format!("The {} command to execute", executor.interpreter().name())
+                        },
+                        "timeout": {
+                            "type": "integer",
+                            "description": "Optional timeout in seconds, up to the configured maximum; the command and everything it started are killed when it expires"
+                        },
+                        "reset": {
+                            "type": "boolean",
+                            "description": "Restart the shell session first, discarding its working directory and environment"
                         }
                     },
                     "required": ["command"]
@@ -38,13 +77,21 @@ pub fn get_tool_definitions() -> Vec<ToolDefinition> {
             tool_type: "function".to_string(),
             function: crate::llm::provider::FunctionDefinition {
                 name: "read".to_string(),
-                description: "Read the contents of a file. Returns the file content as a string.".to_string(),
+                description: "Read a text file. Returns its lines numbered like cat -n; long files are cut off with a note saying which offset to continue from. Binary files are refused.".to_string(),
                 parameters: json!({
                     "type": "object",
                     "properties": {
                         "path": {
                             "type": "string",
                             "description": "The path to the file to read"
+                        },
+                        "offset": {
+                            "type": "integer",
+                            "description": "Optional 1-based line number to start reading from"
+                        },
+                        "limit": {
+                            "type": "integer",
+                            "description": "Optional maximum number of lines to read"
                         }
                     },
                     "required": ["path"]
@@ -72,68 +119,180 @@ pub fn get_tool_definitions() -> Vec<ToolDefinition> {
                 }),
             },
         },
+        ToolDefinition {
+            tool_type: "function".to_string(),
+            function: crate::llm::provider::FunctionDefinition {
+                name: "edit".to_string(),
+                description: "Replace exact text in a file and return a diff of the change. old_string must match the file exactly, including indentation, and be unique unless replace_all is set; add surrounding lines to disambiguate. Pass edits to make several changes at once: they apply in order, and nothing is written if any fails. Prefer this over rewriting files with write.".to_string(),
+                parameters: json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "The path to the file to edit"
+                        },
+                        "old_string": {
+                            "type": "string",
+                            "description": "The exact text to replace, without the line numbers shown by read"
+                        },
+                        "new_string": {
+                            "type": "string",
+                            "description": "The text to replace it with"
+                        },
+                        "replace_all": {
+                            "type": "boolean",
+                            "description": "Replace every occurrence of old_string instead of requiring exactly one"
+                        },
+                        "edits": {
+                            "type": "array",
+                            "description": "Several replacements to apply in order, instead of old_string and new_string",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "old_string": { "type": "string" },
+                                    "new_string": { "type": "string" },
+                                    "replace_all": { "type": "boolean" }
+                                },
+                                "required": ["old_string", "new_string"]
+                            }
+                        }
+                    },
+                    "required": ["path"]
+                }),
+            },
+        },
+        ToolDefinition {
+            tool_type: "function".to_string(),
+            function: crate::llm::provider::FunctionDefinition {
+                name: "apply_patch".to_string(),
+                description: "Apply a unified diff (as from diff -u or git diff) to one or more files. Files can be added (--- /dev/null), deleted (+++ /dev/null) or renamed (rename from/rename to). Hunks are placed near their line numbers even if the file has shifted, tolerating whitespace differences. Nothing is changed unless every hunk applies; failing hunks are reported.".to_string(),
+                parameters: json!({
+                    "type": "object",
+                    "properties": {
+                        "patch": {
+                            "type": "string",
+                            "description": "The unified diff, with ---/+++ file headers and @@ hunks"
+                        }
+                    },
+                    "required": ["patch"]
+                }),
+            },
+        },
         ToolDefinition {
             tool_type: "function".to_string(),
             function: crate::llm::provider::FunctionDefinition {
                 name: "list".to_string(),
-                description: "List files in the current directory. Optionally filter by pattern.".to_string(),
+                description: "List files and directories with their type and size. Hidden and .gitignored files are skipped. Use this instead of ls or find.".to_string(),
                 parameters: json!({
                     "type": "object",
                     "properties": {
                         "pattern": {
                             "type": "string",
-                            "description": "Optional glob pattern to filter files (e.g., '*.rs')"
+                            "description": "Glob relative to path: '*' lists one directory, '**/*.rs' searches recursively, 'src/*/mod.rs' matches at a fixed depth (default '*')"
+                        },
+                        "path": {
+                            "type": "string",
+                            "description": "Directory to list (default: the current directory)"
+                        },
+                        "sort": {
+                            "type": "string",
+                            "enum": ["name", "mtime"],
+                            "description": "Sort by path, or by modification time with the newest first (default 'name')"
+                        },
+                        "limit": {
+                            "type": "integer",
+                            "description": format!("Maximum number of entries to return (default {})", list::DEFAULT_LIMIT)
                         }
                     },
                     "required": []
                 }),
             },
         },
+        ToolDefinition {
+            tool_type: "function".to_string(),
+            function: crate::llm::provider::FunctionDefinition {
+                name: "search".to_string(),
+                description: "Search file contents with a regular expression and return the matching lines with their paths and line numbers. Hidden, .gitignored and binary files are skipped. Use this instead of grep or rg.".to_string(),
+                parameters: json!({
+                    "type": "object",
+                    "properties": {
+                        "pattern": {
+                            "type": "string",
+                            "description": "Regular expression (Rust regex syntax) to search for"
+                        },
+                        "path": {
+                            "type": "string",
+                            "description": "File or directory to search (default: the current directory)"
+                        },
+                        "case_insensitive": {
+                            "type": "boolean",
+                            "description": "Ignore case when matching"
+                        },
+                        "multiline": {
+                            "type": "boolean",
+                            "description": "Let the pattern match across lines, with '.' matching newlines"
+                        },
+                        "glob": {
+                            "type": "string",
+                            "description": "Only search files matching this glob, e.g. '*.rs' or 'src/**/*.ts'"
+                        },
+                        "type": {
+                            "type": "string",
+                            "description": "Only search files of this type, e.g. 'rust', 'py', 'js', 'md'"
+                        },
+                        "context": {
+                            "type": "integer",
+                            "description": "Number of lines to show before and after each match"
+                        },
+                        "offset": {
+                            "type": "integer",
+                            "description": "Number of matches to skip, to fetch the next page"
+                        },
+                        "limit": {
+                            "type": "integer",
+                            "description": format!("Maximum number of matches to return (default {})", search::DEFAULT_LIMIT)
+                        }
+                    },
+                    "required": ["pattern"]
+                }),
+            },
+        },
     ]
 }
 
+/// Whether a tool only inspects the system and never modifies it
+pub fn is_read_only_tool(tool_name: &str) -> bool {
+    matches!(tool_name, "read" | "list" | "search")
+}
+
 /// Execute a tool call
-pub fn execute_tool(
+pub async fn execute_tool(
     tool_name: &str,
     arguments: &str,
     executor: &ShellExecutor,
 ) -> Result<String> {
     tracing::info!("Executing tool: {} with args: {}", tool_name, arguments);
 
-    match tool_name {
-        "bash" => {
-            let args: serde_json::Value = serde_json::from_str(arguments)?;
-            let command = args["command"]
-                .as_str()
-                .context("Missing 'command' argument")?;
-
-            let result = executor.execute(command)?;
-
-            let output = if result.success {
-                format!("Exit code: {}\n\nStdout:\n{}\n\nStderr:\n{}",
-                    result.exit_code,
-                    result.stdout,
-                    result.stderr
-                )
-            } else {
-                format!("Command failed with exit code: {}\n\nStdout:\n{}\n\nStderr:\n{}",
-                    result.exit_code,
-                    result.stdout,
-                    result.stderr
-                )
-            };
+    if tool_name == "bash" {
+        let result = run_bash(arguments, executor, |_| {}, CancellationToken::new()).await?;
+        return bash_tool_result(&result);
+    }
 
-            Ok(output)
-        }
+    let output = run_tool(tool_name, arguments, executor).await?;
+    Ok(executor.bound_output(output))
+}
 
+async fn run_tool(tool_name: &str, arguments: &str, executor: &ShellExecutor) -> Result<String> {
+    match tool_name {
         "read" => {
             let args: serde_json::Value = serde_json::from_str(arguments)?;
             let path = args["path"]
                 .as_str()
                 .context("Missing 'path' argument")?;
 
-            let content = executor.read_file(path)?;
-            Ok(content)
+            let offset = args["offset"].as_u64().unwrap_or(1) as usize;
+            let limit = args["limit"].as_u64().map(|limit| limit as usize);
+            executor.read_lines(path, offset, limit)
         }
 
         "write" => {
@@ -149,14 +308,189 @@ pub fn execute_tool(
             Ok(format!("Successfully wrote to file: {}", path))
         }
 
+        "edit" => {
+            let args: serde_json::Value = serde_json::from_str(arguments)?;
+            let path = args["path"]
+                .as_str()
+                .context("Missing 'path' argument")?;
+
+            let edits: Vec<Edit> = match args.get("edits") {
+                Some(edits) if !edits.is_null() => serde_json::from_value(edits.clone())
+                    .context("Invalid 'edits' argument; each edit needs old_string and new_string")?,
+                _ => vec![Edit {
+                    old_string: args["old_string"]
+                        .as_str()
+                        .context("Missing 'old_string' argument")?
+                        .to_string(),
+                    new_string: args["new_string"]
+                        .as_str()
+                        .context("Missing 'new_string' argument")?
+                        .to_string(),
+                    replace_all: args["replace_all"].as_bool().unwrap_or(false),
+                }],
+            };
+
+            executor.edit_file(path, &edits)
+        }
+
+        "apply_patch" => {
+            let args: serde_json::Value = serde_json::from_str(arguments)?;
+            let patch = args["patch"]
+                .as_str()
+                .context("Missing 'patch' argument")?;
+
+            executor.apply_patch(patch)
+        }
+
         "list" => {
             let args: serde_json::Value = serde_json::from_str(arguments)?;
-            let pattern = args["pattern"].as_str();
+            let mut options = ListOptions::default();
+            if let Some(pattern) = args["pattern"].as_str() {
+                options.pattern = pattern.to_string();
+            }
+            if let Some(sort) = args["sort"].as_str() {
+                options.sort = ListSort::parse(sort)?;
+            }
+            if let Some(limit) = args["limit"].as_u64() {
+                options.limit = limit as usize;
+            }
 
-            let files = executor.list_files(pattern)?;
-            Ok(files.join("\n"))
+            let listing = executor.list_files(args["path"].as_str(), options).await?;
+            Ok(listing.format())
         }
 
+        "search" => Ok(execute_search(arguments, executor).await?.format()),
+
         _ => anyhow::bail!("Unknown tool: {}", tool_name),
     }
 }
+
+/// Run the search tool, returning the matches themselves so callers can
+/// render or pass them on as structured data
+pub async fn execute_search(arguments: &str, executor: &ShellExecutor) -> Result<SearchResults> {
+    let args: serde_json::Value = serde_json::from_str(arguments)?;
+    let pattern = args["pattern"]
+        .as_str()
+        .context("Missing 'pattern' argument")?;
+
+    let mut options = SearchOptions::new(pattern);
+    options.case_insensitive = args["case_insensitive"].as_bool().unwrap_or(false);
+    options.multiline = args["multiline"].as_bool().unwrap_or(false);
+    options.glob = args["glob"].as_str().map(str::to_string);
+    options.file_type = args["type"].as_str().map(str::to_string);
+    if let Some(context) = args["context"].as_u64() {
+        options.context = context as usize;
+    }
+    if let Some(offset) = args["offset"].as_u64() {
+        options.offset = offset as usize;
+    }
+    if let Some(limit) = args["limit"].as_u64() {
+        options.limit = limit as usize;
+    }
+
+    executor.search_files(args["path"].as_str(), options).await
+}
+
+/// Run the bash tool asynchronously, reporting output as it arrives.
+/// The result's output is already cut down to the executor's limits.
+pub async fn execute_bash_streaming<F>(
+    arguments: &str,
+    executor: &ShellExecutor,
+    on_output: F,
+    cancel: CancellationToken,
+) -> Result<ExecutionResult>
+where
+    F: FnMut(&str) + Send,
+{
+    tracing::info!("Executing tool: bash with args: {}", arguments);
+    run_bash(arguments, executor, on_output, cancel).await
+}
+
+async fn run_bash<F>(
+    arguments: &str,
+    executor: &ShellExecutor,
+    on_output: F,
+    cancel: CancellationToken,
+) -> Result<ExecutionResult>
+where
+    F: FnMut(&str) + Send,
+{
+    let args: serde_json::Value = serde_json::from_str(arguments)?;
+    let command = args["command"]
+        .as_str()
+        .context("Missing 'command' argument")?;
+
+    reset_if_requested(executor, &args).await;
+    let result = with_call_timeout(executor, &args)
+        .execute_streaming(command, on_output, cancel)
+        .await?;
+    Ok(executor.bound_result(result))
+}
+
+/// Honor the `reset` argument by restarting the persistent shell, if any
+async fn reset_if_requested(executor: &ShellExecutor, args: &serde_json::Value) {
+    if args["reset"].as_bool() == Some(true) {
+        if let Some(shell) = executor.persistent_shell() {
+            shell.reset().await;
+        }
+    }
+}
+
+/// Apply the per-call `timeout` argument, if any, to a copy of the
+/// executor. It can only shorten the configured timeout, not extend it.
+fn with_call_timeout(executor: &ShellExecutor, args: &serde_json::Value) -> ShellExecutor {
+    match args["timeout"].as_u64() {
+        Some(secs) if secs > 0 => {
+            let timeout = Duration::from_secs(secs).min(executor.timeout());
+            executor.clone().with_timeout(timeout)
+        }
+        _ => executor.clone(),
+    }
+}
+
+/// The bash tool's result text. A command the sandbox stopped is reported
+/// as a tool error so the model doesn't retry it as an ordinary failure.
+pub fn bash_tool_result(result: &ExecutionResult) -> Result<String> {
+    let text = format_execution_result(result);
+    match result.sandbox_violation {
+        Some(_) => Err(anyhow::anyhow!(text)),
+        None => Ok(text),
+    }
+}
+
+pub fn format_execution_result(result: &ExecutionResult) -> String {
+    let status = if let Some(timeout) = result.timeout {
+        format!("Command timed out after {}s and was killed", timeout.as_secs())
+    } else if let Some(signal) = result.signal_name() {
+        format!(
+            "Command was killed by {}{}",
+            signal,
+            if result.core_dumped { " (core dumped)" } else { "" }
+        )
+    } else if result.success {
+        format!("Exit code: {}", result.exit_code)
+    } else {
+        format!("Command failed with exit code: {}", result.exit_code)
+    };
+
+    let mut details = vec![format!("took {:.2}s", result.duration.as_secs_f64())];
+    if result.truncated {
+        details.push("output truncated".to_string());
+    }
+
+    let partial = if result.timed_out() { " (partial)" } else { "" };
+    let violation = match &result.sandbox_violation {
+        Some(violation) => format!("Sandbox violation: {}\n", violation),
+        None => String::new(),
+    };
+    format!(
+        "{}{} ({})\n\nStdout{}:\n{}\n\nStderr{}:\n{}",
+        violation,
+        status,
+        details.join(", "),
+        partial,
+        result.stdout,
+        partial,
+        result.stderr
+    )
+}